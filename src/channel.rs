@@ -0,0 +1,54 @@
+use emulator::Emulator;
+use gbc::gamepad::InputEvent;
+
+use std::sync::mpsc::{self, Sender, Receiver};
+
+/// Drives an `Emulator` through channels, for frontends that run it on its
+/// own thread. Frames are copied out, input arrives as `InputEvent`s.
+pub struct ChannelAdaptor {
+    emulator: Emulator,
+    frame_tx: Sender<Box<[u32]>>,
+    input_rx: Receiver<InputEvent>,
+}
+
+impl ChannelAdaptor {
+    pub fn new(emulator: Emulator) -> (ChannelAdaptor, Receiver<Box<[u32]>>, Sender<InputEvent>) {
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let (input_tx, input_rx) = mpsc::channel();
+
+        let adaptor = ChannelAdaptor {
+            emulator: emulator,
+            frame_tx: frame_tx,
+            input_rx: input_rx,
+        };
+        (adaptor, frame_rx, input_tx)
+    }
+
+    /// Applies pending input and runs a frame. Returns false once the frame
+    /// receiver has been dropped.
+    pub fn run_frame(&mut self) -> bool {
+        let mut buttons = self.emulator.buttons();
+        while let Ok(event) = self.input_rx.try_recv() {
+            buttons = event.apply(buttons)
+        }
+        self.emulator.set_buttons(buttons);
+
+        self.emulator.run_frame();
+        let frame = self.emulator.framebuffer().to_vec().into_boxed_slice();
+        self.frame_tx.send(frame).is_ok()
+    }
+
+    /// Runs frames until the frame receiver is dropped
+    pub fn run(mut self) -> Emulator {
+        while self.run_frame() {}
+        self.emulator
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
@@ -0,0 +1,265 @@
+use gbc::GameboyType;
+use gbc::boot_rom::{BootRom, BootRomError};
+use gbc::cart::{Cart, CartError, HeaderError};
+use gbc::cpu::{Cpu, Lockup, Status};
+use gbc::gamepad::Gamepad;
+use gbc::interconnect::Interconnect;
+use gbc::palette::DmgPalette;
+use gbc::registers::Registers;
+use gbc::ppu::{Ppu, Renderer};
+use gbc::save_state::{self, SaveState, StateWriter, StateReader, StateError};
+use gbc::spu::Spu;
+
+use std::fmt;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Length of a frame while the LCD is on
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+/// Settings an `Emulator` is created with
+#[derive(Debug,Clone)]
+pub struct Options {
+    /// Hardware to emulate. Ignored when a boot ROM is given, its size
+    /// decides instead.
+    pub gameboy_type: GameboyType,
+    /// DMG or CGB boot ROM to run before the cartridge
+    pub boot_rom: Option<Box<[u8]>>,
+    pub renderer: Renderer,
+    pub dmg_palette: DmgPalette,
+    /// Blocks CPU access to VRAM and OAM while the PPU uses them
+    pub access_locking: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            gameboy_type: GameboyType::Dmg,
+            boot_rom: None,
+            renderer: Renderer::Scanline,
+            dmg_palette: DmgPalette::default(),
+            access_locking: true,
+        }
+    }
+}
+
+/// Why an `Emulator` couldn't be created
+#[derive(Debug)]
+pub enum LoadError {
+    Rom(CartError),
+    BootRom(BootRomError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Rom(ref e) => write!(f, "{}", e),
+            LoadError::BootRom(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A complete Game Boy, running a cartridge
+pub struct Emulator {
+    gameboy_type: GameboyType,
+    boot_rom_hash: Option<u64>,
+    renderer: Renderer,
+    access_locking: bool,
+    cpu: Cpu,
+    buttons: u8,
+}
+
+impl Emulator {
+    pub fn new(rom: Box<[u8]>, options: Options) -> Result<Emulator, LoadError> {
+        let cart = Cart::new(rom).map_err(LoadError::Rom)?;
+        let boot_rom_hash = options.boot_rom.as_ref().map(|boot_rom| save_state::hash(boot_rom));
+        let boot_rom = match options.boot_rom {
+            Some(bytes) => Some(BootRom::new(bytes).map_err(LoadError::BootRom)?),
+            None => None,
+        };
+        let gameboy_type = match boot_rom {
+            Some(ref boot_rom) => boot_rom.gameboy_type(),
+            None => options.gameboy_type,
+        };
+
+        let mut ppu = Ppu::new(gameboy_type);
+        ppu.set_renderer(options.renderer);
+        ppu.set_dmg_palette(options.dmg_palette);
+        ppu.set_access_locking(options.access_locking);
+
+        let interconnect = Interconnect::new(gameboy_type,
+                                             cart,
+                                             boot_rom,
+                                             ppu,
+                                             Spu::new(),
+                                             Gamepad::new());
+
+        Ok(Emulator {
+            gameboy_type: gameboy_type,
+            boot_rom_hash: boot_rom_hash,
+            renderer: options.renderer,
+            access_locking: options.access_locking,
+            cpu: Cpu::new(gameboy_type, interconnect),
+            buttons: 0,
+        })
+    }
+
+    pub fn gameboy_type(&self) -> GameboyType {
+        self.gameboy_type
+    }
+
+    /// Hash of the boot ROM the emulator was started with, if any
+    pub fn boot_rom_hash(&self) -> Option<u64> {
+        self.boot_rom_hash
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn access_locking(&self) -> bool {
+        self.access_locking
+    }
+
+    pub fn cart(&self) -> &Cart {
+        self.cpu.bus().cart()
+    }
+
+    /// Whether the boot ROM of this hardware type would accept the cartridge
+    pub fn check_header(&self) -> Result<(), HeaderError> {
+        self.cart().check_header(self.gameboy_type)
+    }
+
+    /// Runs until the next frame is complete. Returns the number of cycles run.
+    pub fn run_frame(&mut self) -> u32 {
+        self.cpu.bus_mut().spu_mut().clear_samples();
+
+        let mut cycles = 0;
+        loop {
+            cycles += self.step();
+            if self.frame_ready() {
+                self.clear_frame_ready();
+                return cycles;
+            }
+        }
+    }
+
+    /// Like `run_frame`, but also returns right after LD B,B executes while
+    /// no breakpoint is pending. Returns whether the frame is complete.
+    pub fn run_to_breakpoint(&mut self) -> bool {
+        self.cpu.bus_mut().spu_mut().clear_samples();
+
+        let pending = self.breakpoint_hit();
+        loop {
+            self.step();
+            if self.frame_ready() {
+                self.clear_frame_ready();
+                return true;
+            }
+            if !pending && self.breakpoint_hit() {
+                return false;
+            }
+        }
+    }
+
+    /// Runs a single instruction. Returns the number of cycles it took.
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step()
+    }
+
+    /// Whether a frame was completed since the last `clear_frame_ready`, for
+    /// callers driving the emulator with `step`
+    pub fn frame_ready(&self) -> bool {
+        self.cpu.bus().ppu().frame_ready()
+    }
+
+    pub fn clear_frame_ready(&mut self) {
+        self.cpu.bus_mut().ppu_mut().clear_frame_ready()
+    }
+
+    /// Last complete frame, 160x144 pixels as 0xAARRGGBB
+    pub fn framebuffer(&self) -> &[u32] {
+        self.cpu.bus().ppu().framebuffer()
+    }
+
+    /// Sets the pressed buttons, as a mask of `Button::mask` bits
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.cpu.bus_mut().gamepad_mut().set_buttons(buttons);
+        self.buttons = buttons
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Interleaved stereo samples at `spu::SAMPLE_RATE`, produced since the
+    /// start of the last `run_frame`
+    pub fn audio_samples(&self) -> &[i16] {
+        self.cpu.bus().spu().samples()
+    }
+
+    /// Bytes sent over the serial port since `set_serial_capture` enabled
+    /// capturing, nothing is kept by default
+    pub fn serial_output(&self) -> &[u8] {
+        self.cpu.bus().serial_output()
+    }
+
+    pub fn set_serial_capture(&mut self, capture: bool) {
+        self.cpu.bus_mut().set_serial_capture(capture)
+    }
+
+    /// Reads memory without using any cycles. Unlike the CPU, this also sees
+    /// VRAM and OAM while the PPU or OAM DMA have them locked.
+    pub fn read_memory(&mut self, addr: u16) -> u8 {
+        self.cpu.bus_mut().peek(addr)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    /// Whether LD B,B, the breakpoint instruction of test ROMs, was executed
+    /// since the last `clear_breakpoint`
+    pub fn breakpoint_hit(&self) -> bool {
+        self.cpu.breakpoint_hit()
+    }
+
+    pub fn clear_breakpoint(&mut self) {
+        self.cpu.clear_breakpoint()
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.cpu.bus_mut().ppu_mut().set_dmg_palette(palette)
+    }
+
+    /// Snapshot of the whole machine, to be restored with `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(self.gameboy_type, self.cart().hash());
+        self.cpu.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restores a snapshot made by `save_state`, for the same ROM and hardware
+    /// type. The machine is left unchanged if the state can't be loaded.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state, self.gameboy_type, self.cart().hash())?;
+        // Loaded into a copy, which only replaces the machine once all of the
+        // state has been read and checked
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&mut reader)?;
+        reader.finish()?;
+
+        self.cpu = cpu;
+        self.buttons = self.cpu.bus().gamepad().buttons();
+        Ok(())
+    }
+
+    pub fn status(&self) -> Status {
+        self.cpu.status()
+    }
+
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.cpu.lockup()
+    }
+}
@@ -0,0 +1,11 @@
+// ROMs shared by the unit tests, tests/common has the same for the
+// integration tests
+
+/// A ROM without MBC or RAM, filled with `fill` and looping with JR -2 at
+/// the entry point
+pub fn test_rom(fill: u8) -> Box<[u8]> {
+    let mut rom = vec![fill; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+    rom[0x147..0x14a].copy_from_slice(&[0, 0, 0]);
+    rom.into_boxed_slice()
+}
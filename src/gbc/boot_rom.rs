@@ -0,0 +1,65 @@
+use super::GameboyType;
+
+use std::boxed::Box;
+use std::fmt;
+
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// A boot ROM dump. DMG, MGB and SGB boot ROMs are mapped over
+/// 0x0000-0x00ff, CGB and AGB boot ROMs additionally over 0x0200-0x08ff,
+/// until the boot ROM unmaps itself by writing to 0xff50.
+#[derive(Clone)]
+pub struct BootRom {
+    bytes: Box<[u8]>,
+}
+
+#[derive(Debug)]
+pub enum BootRomError {
+    /// Neither the size of a DMG nor of a CGB boot ROM
+    UnsupportedSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootRomError::UnsupportedSize(size) => {
+                write!(f,
+                       "unsupported boot ROM size 0x{:x}, expected 0x{:x} or 0x{:x}",
+                       size,
+                       DMG_BOOT_ROM_SIZE,
+                       CGB_BOOT_ROM_SIZE)
+            }
+        }
+    }
+}
+
+impl BootRom {
+    pub fn new(bytes: Box<[u8]>) -> Result<BootRom, BootRomError> {
+        match bytes.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom { bytes: bytes }),
+            size => Err(BootRomError::UnsupportedSize(size)),
+        }
+    }
+
+    /// The kind of hardware the boot ROM expects to run on
+    pub fn gameboy_type(&self) -> GameboyType {
+        if self.bytes.len() == CGB_BOOT_ROM_SIZE {
+            GameboyType::Cgb
+        } else {
+            GameboyType::Dmg
+        }
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        match addr {
+            0x0000...0x00ff => true,
+            0x0200...0x08ff => self.bytes.len() == CGB_BOOT_ROM_SIZE,
+            _ => false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+}
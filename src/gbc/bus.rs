@@ -0,0 +1,63 @@
+use super::interconnect::Interconnect;
+
+/// Everything the CPU is connected to. `Interconnect` is the bus of the
+/// Game Boy, others test the CPU on its own or wrap a bus to trace accesses.
+///
+/// Only memory and timing are required, a bus without an interrupt
+/// controller or cartridge can leave the rest to the defaults.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    /// Runs the rest of the machine for `cycles` clock cycles. The CPU ticks
+    /// 4 cycles before every memory access.
+    fn tick(&mut self, cycles: u32);
+
+    /// Interrupts that are both requested and enabled, bit 0 is VBLANK
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    /// Clears the request of interrupt `int` when the CPU dispatches it
+    fn acknowledge_interrupt(&mut self, _int: u32) {}
+
+    /// Clock cycles the CPU can skip while halted, until something could
+    /// raise an interrupt
+    fn cycles_until_event(&self) -> u32 {
+        4
+    }
+
+    /// ROM bank mapped at `addr`, for reporting lockups
+    fn rom_bank(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
+
+impl Bus for Interconnect {
+    fn read(&mut self, addr: u16) -> u8 {
+        Interconnect::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        Interconnect::write(self, addr, val)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycle_flush(cycles)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.int_flags & self.int_enable & 0x1f
+    }
+
+    fn acknowledge_interrupt(&mut self, int: u32) {
+        self.int_flags &= !(1 << int)
+    }
+
+    fn cycles_until_event(&self) -> u32 {
+        Interconnect::cycles_until_event(self)
+    }
+
+    fn rom_bank(&self, addr: u16) -> Option<usize> {
+        Interconnect::rom_bank(self, addr)
+    }
+}
@@ -2,15 +2,27 @@ use std::fmt;
 use std::fmt::Debug;
 use std::string::String;
 use std::boxed::Box;
+use std::sync::Arc;
 
 use super::mbc::Mbc;
 use super::mbc::MbcType;
 use super::mbc::RamInfo;
 use super::mbc::MbcInfo;
 use super::GameboyType;
+use super::save_state::{self, SaveState, StateWriter, StateReader, StateError};
+
+const NINTENDO_LOGO: [u8; 48] = [0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00,
+                                 0x83, 0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89,
+                                 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb,
+                                 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f,
+                                 0xbb, 0xb9, 0x33, 0x3e];
+
+/// The smallest cartridges have two 16 KiB ROM banks
+const MIN_ROM_SIZE: usize = 0x8000;
 
 pub struct Cart {
-    bytes: Box<[u8]>,
+    // Shared with clones, which only copy the MBC state
+    bytes: Arc<[u8]>,
     mbc: Box<Mbc>,
 }
 
@@ -18,48 +30,128 @@ pub struct Cart {
 pub enum DestinationCode {
     Japanese,
     NonJapanese,
+    Unknown(u8),
+}
+
+/// A ROM the emulator can't run
+#[derive(Debug)]
+pub enum CartError {
+    TooSmall(usize),
+    UnsupportedType(u8),
+    UnsupportedRamSize(u8),
+}
+
+impl fmt::Display for CartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartError::TooSmall(size) => {
+                write!(f,
+                       "ROM is {} bytes, cartridges have at least {}",
+                       size,
+                       MIN_ROM_SIZE)
+            }
+            CartError::UnsupportedType(code) => {
+                write!(f, "unsupported cartridge type 0x{:02x}", code)
+            }
+            CartError::UnsupportedRamSize(code) => {
+                write!(f, "unsupported cartridge RAM size 0x{:02x}", code)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeaderError {
+    /// The ROM ends before the header does
+    Truncated,
+    Logo,
+    Checksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Truncated => write!(f, "header is truncated"),
+            HeaderError::Logo => write!(f, "Nintendo logo mismatch"),
+            HeaderError::Checksum { expected, actual } => {
+                write!(f,
+                       "header checksum is 0x{:02x}, expected 0x{:02x}",
+                       actual,
+                       expected)
+            }
+        }
+    }
 }
 
 impl Cart {
-    pub fn new(bytes: Box<[u8]>) -> Cart {
-        let mbc_info = Cart::get_mbc_info(&bytes);
+    pub fn new(bytes: Box<[u8]>) -> Result<Cart, CartError> {
+        if bytes.len() < MIN_ROM_SIZE {
+            return Err(CartError::TooSmall(bytes.len()));
+        }
+        let mbc_info = Cart::get_mbc_info(&bytes)?;
         let mbc = super::mbc::new_mbc(mbc_info);
-        Cart {
-            bytes: bytes,
+        Ok(Cart {
+            bytes: Arc::from(bytes),
             mbc: mbc,
-        }
+        })
     }
 
     pub fn title(&self) -> String {
-        let mut title = Vec::new();
-        for i in 0x0134..0x0143 {
-            title.push(self.bytes[i]);
-        }
-        String::from_utf8(title).unwrap()
+        String::from_utf8_lossy(&self.bytes[0x0134..0x0143]).into_owned()
     }
 
     pub fn mbc_info(&self) -> MbcInfo {
-        Cart::get_mbc_info(&self.bytes)
+        Cart::get_mbc_info(&self.bytes).expect("checked by Cart::new")
     }
 
-    fn get_mbc_info(bytes: &Box<[u8]>) -> MbcInfo {
+    fn get_mbc_info(bytes: &[u8]) -> Result<MbcInfo, CartError> {
+        if bytes[0x0149] > 4 {
+            return Err(CartError::UnsupportedRamSize(bytes[0x0149]));
+        }
         let ram_info = if Cart::get_ram_size(&bytes) != 0 {
-            Some(RamInfo::new(Cart::get_ram_size(&bytes), Cart::get_ram_bank_count(&bytes)))
+            Some(RamInfo::new(Cart::get_ram_size(&bytes)))
         } else {
             None
         };
-        match bytes[0x0147] {
-            0x00 => MbcInfo::new(MbcType::None, ram_info, false),
-            0x01 => MbcInfo::new(MbcType::Mbc1, ram_info, false),
-            0x02 => MbcInfo::new(MbcType::Mbc1, ram_info, false),
-            0x03 => MbcInfo::new(MbcType::Mbc1, ram_info, true),
-            0x13 => MbcInfo::new(MbcType::Mbc3, ram_info, true),
-            0x19 => MbcInfo::new(MbcType::Mbc5, ram_info, false),
-            0x1b => MbcInfo::new(MbcType::Mbc5, ram_info, true),
-            _ => panic!("Unsupported mbc_info: 0x{:x}", bytes[0x0147]),
+        Ok(match bytes[0x0147] {
+            // With and without RAM and battery
+            0x00 => MbcInfo::new(MbcType::None, ram_info),
+            0x01 | 0x02 | 0x03 => MbcInfo::new(MbcType::Mbc1, ram_info),
+            0x13 => MbcInfo::new(MbcType::Mbc3, ram_info),
+            0x19 | 0x1b => MbcInfo::new(MbcType::Mbc5, ram_info),
+            code => return Err(CartError::UnsupportedType(code)),
+        })
+    }
+
+    /// Performs the same header checks as the boot ROM, which locks up
+    /// instead of starting the cartridge if any of them fail.
+    pub fn check_header(&self, gb_type: GameboyType) -> Result<(), HeaderError> {
+        // The CGB boot ROM only checks the top half of the logo
+        let logo_size = match gb_type {
+            GameboyType::Cgb => NINTENDO_LOGO.len() / 2,
+            GameboyType::Dmg => NINTENDO_LOGO.len(),
+        };
+        let header = match self.bytes.get(0x0100..0x0150) {
+            Some(header) => header,
+            None => return Err(HeaderError::Truncated),
+        };
+        if header[0x04..0x04 + logo_size] != NINTENDO_LOGO[..logo_size] {
+            return Err(HeaderError::Logo);
+        }
+
+        let checksum = header[0x34..0x4d]
+            .iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        if checksum != header[0x4d] {
+            return Err(HeaderError::Checksum {
+                expected: checksum,
+                actual: header[0x4d],
+            });
         }
+        Ok(())
     }
 
+    /// Size given by the header, or the size of the ROM for unknown codes
     pub fn rom_size(&self) -> u32 {
         match self.bytes[0x0148] {
             0 => 1024 * 32,
@@ -69,10 +161,17 @@ impl Cart {
             4 => 1024 * 512,
             5 => 1024 * 1024,
             6 => 1024 * 1024 * 2,
-            _ => panic!("Unsupported rom size: {:x}", self.bytes[0x0148]),
+            7 => 1024 * 1024 * 4,
+            8 => 1024 * 1024 * 8,
+            _ => self.bytes.len() as u32,
         }
     }
 
+    /// Hash of the whole ROM, identifies the game in save states
+    pub fn hash(&self) -> u64 {
+        save_state::hash(&self.bytes)
+    }
+
     pub fn rom_bank_count(&self) -> u32 {
         self.rom_size() / (1024 * 16)
     }
@@ -82,7 +181,7 @@ impl Cart {
         Cart::get_ram_size(&self.bytes)
     }
 
-    fn get_ram_size(bytes: &Box<[u8]>) -> u32 {
+    fn get_ram_size(bytes: &[u8]) -> u32 {
         match bytes[0x149] {
             0 => 0,
             1 => 1024 * 2,
@@ -98,7 +197,7 @@ impl Cart {
         Cart::get_ram_bank_count(&self.bytes)
     }
 
-    fn get_ram_bank_count(bytes: &Box<[u8]>) -> u32 {
+    fn get_ram_bank_count(bytes: &[u8]) -> u32 {
         match bytes[0x0149] {
             0 => 0,
             1 | 2 => 1,
@@ -112,7 +211,7 @@ impl Cart {
         match self.bytes[0x014a] {
             0 => DestinationCode::Japanese,
             1 => DestinationCode::NonJapanese,
-            _ => panic!("Unsupported destination code"),
+            code => DestinationCode::Unknown(code),
         }
     }
 
@@ -129,6 +228,13 @@ impl Cart {
         self.mbc.read(&self.bytes, addr)
     }
 
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000...0x3fff => 0,
+            _ => self.mbc.rom_bank(),
+        }
+    }
+
     pub fn write(&mut self, addr: u16, val: u8) {
         self.mbc.write(addr, val)
     }
@@ -142,6 +248,26 @@ impl Cart {
     }
 }
 
+impl Clone for Cart {
+    fn clone(&self) -> Cart {
+        Cart {
+            bytes: self.bytes.clone(),
+            mbc: self.mbc.clone_box(),
+        }
+    }
+}
+
+impl SaveState for Cart {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"CART", |w| self.mbc.save_state(w))
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mbc = &mut self.mbc;
+        reader.section(*b"CART", |r| mbc.load_state(r))
+    }
+}
+
 impl Debug for Cart {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,
@@ -159,3 +285,34 @@ impl Debug for Cart {
                self.destination_code())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::{Cart, DestinationCode};
+    use fixtures::test_rom;
+
+    #[test]
+    fn unusual_header_fields() {
+        let mut rom = test_rom(0);
+        rom[0x0134..0x0143].copy_from_slice(b"\xffTITLE\0\0\0\0\0\0\0\0\0");
+        rom[0x0147] = 0x19; // MBC5
+        rom[0x0148] = 8;
+        rom[0x014a] = 2;
+        let cart = Cart::new(rom).unwrap();
+
+        assert_eq!(cart.title(), "\u{fffd}TITLE\0\0\0\0\0\0\0\0\0");
+        assert_eq!(cart.rom_size(), 8 * 1024 * 1024);
+        match cart.destination_code() {
+            DestinationCode::Unknown(2) => {}
+            code => panic!("unexpected destination code {:?}", code),
+        }
+        assert!(format!("{:?}", cart).contains("Unknown(2)"));
+    }
+
+    #[test]
+    fn clones_share_the_rom() {
+        let cart = Cart::new(test_rom(0)).unwrap();
+        assert!(Arc::ptr_eq(&cart.bytes, &cart.clone().bytes));
+    }
+}
@@ -1,16 +1,58 @@
 use super::interconnect::Interconnect;
+use super::bus::Bus;
 use super::registers::{Registers, Reg8, Reg16};
 use super::opcode::{CB_OPCODE_TIMES, OPCODE_TIMES, OPCODE_COND_TIMES};
 use super::GameboyType;
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
+use std::fmt;
 use std::u8;
 use std::u16;
+use std::u32;
+
+/// An illegal opcode was executed, which hangs the CPU until power off.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Lockup {
+    pub opcode: u8,
+    pub pc: u16,
+    /// ROM bank mapped at `pc`, if `pc` is in ROM
+    pub bank: Option<usize>,
+}
 
-pub struct Cpu {
+impl fmt::Display for Lockup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => {
+                write!(f,
+                       "illegal opcode 0x{:02x} at 0x{:04x} (bank {})",
+                       self.opcode,
+                       self.pc,
+                       bank)
+            }
+            None => write!(f, "illegal opcode 0x{:02x} at 0x{:04x}", self.opcode, self.pc),
+        }
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Status {
+    Running,
+    Halted,
+    Locked(Lockup),
+}
+
+#[derive(Clone)]
+pub struct Cpu<B: Bus = Interconnect> {
     reg: Registers,
-    interconnect: Interconnect,
+    bus: B,
     ime: bool,
+    ime_pending: bool,
     halted: bool,
+    halt_bug: bool,
+    lockup: Option<Lockup>,
+    // LD B,B was executed, which debuggers and test ROMs use as a breakpoint
+    breakpoint: bool,
+    cycles: u32,
 }
 
 struct Imm8;
@@ -31,7 +73,7 @@ enum Cond {
 }
 
 impl Cond {
-    fn is_true(self, cpu: &Cpu) -> bool {
+    fn is_true<B: Bus>(self, cpu: &Cpu<B>) -> bool {
         use self::Cond::*;
         match self {
             Uncond => true,
@@ -51,165 +93,270 @@ enum Timing {
 }
 
 trait Src<T> {
-    fn read(self, cpu: &mut Cpu) -> T;
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> T;
 }
 
 trait Dst<T> {
-    fn write(self, cpu: &mut Cpu, val: T);
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: T);
 }
 
 impl Dst<u8> for Reg8 {
-    fn write(self, cpu: &mut Cpu, val: u8) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u8) {
         cpu.reg.write_u8(self, val)
     }
 }
 
 impl Dst<u16> for Reg16 {
-    fn write(self, cpu: &mut Cpu, val: u16) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u16) {
         cpu.reg.write_u16(self, val)
     }
 }
 
 impl Src<u8> for Reg8 {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         cpu.reg.read_u8(self)
     }
 }
 
 impl Src<u8> for Imm8 {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         cpu.fetch_u8()
     }
 }
 
 impl Src<u16> for Reg16 {
-    fn read(self, cpu: &mut Cpu) -> u16 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u16 {
         cpu.reg.read_u16(self)
     }
 }
 
 impl Src<u16> for Imm16 {
-    fn read(self, cpu: &mut Cpu) -> u16 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u16 {
         cpu.fetch_u16()
     }
 }
 
 impl Src<u8> for ZMem<Imm8> {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         let ZMem(imm) = self;
         let offset = imm.read(cpu) as u16;
         let addr = 0xff00 + offset;
-        cpu.interconnect.read(addr)
+        cpu.read_mem(addr)
     }
 }
 
 impl Dst<u8> for ZMem<Imm8> {
-    fn write(self, cpu: &mut Cpu, val: u8) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u8) {
         let ZMem(imm) = self;
         let offset = imm.read(cpu) as u16;
         let addr = 0xff00 + offset;
-        cpu.interconnect.write(addr, val)
+        cpu.write_mem(addr, val)
     }
 }
 
 impl Src<u8> for ZMem<Reg8> {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         let ZMem(reg) = self;
         let offset = reg.read(cpu) as u16;
         let addr = 0xff00 + offset;
-        cpu.interconnect.read(addr)
+        cpu.read_mem(addr)
     }
 }
 
 impl Dst<u8> for ZMem<Reg8> {
-    fn write(self, cpu: &mut Cpu, val: u8) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u8) {
         let ZMem(reg) = self;
         let offset = reg.read(cpu) as u16;
         let addr = 0xff00 + offset;
-        cpu.interconnect.write(addr, val)
+        cpu.write_mem(addr, val)
     }
 }
 
 impl Dst<u8> for Mem<Reg16> {
-    fn write(self, cpu: &mut Cpu, val: u8) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u8) {
         let Mem(reg) = self;
         let addr = reg.read(cpu);
-        cpu.interconnect.write(addr, val)
+        cpu.write_mem(addr, val)
     }
 }
 
 impl Dst<u8> for Mem<Imm16> {
-    fn write(self, cpu: &mut Cpu, val: u8) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u8) {
         let Mem(imm) = self;
         let addr = imm.read(cpu);
-        cpu.interconnect.write(addr, val)
+        cpu.write_mem(addr, val)
     }
 }
 
 impl Src<u8> for Mem<Imm16> {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         let Mem(imm) = self;
         let addr = imm.read(cpu);
-        cpu.interconnect.read(addr)
+        cpu.read_mem(addr)
     }
 }
 
 impl Dst<u16> for Mem<Imm16> {
-    fn write(self, cpu: &mut Cpu, val: u16) {
+    fn write<B: Bus>(self, cpu: &mut Cpu<B>, val: u16) {
         let Mem(imm) = self;
         let addr = imm.read(cpu);
         let l = val as u8;
         let h = (val >> 8) as u8;
-        cpu.interconnect.write(addr, l);
-        cpu.interconnect.write(addr + 1, h)
+        cpu.write_mem(addr, l);
+        cpu.write_mem(addr.wrapping_add(1), h)
     }
 }
 
 impl Src<u8> for Mem<Reg16> {
-    fn read(self, cpu: &mut Cpu) -> u8 {
+    fn read<B: Bus>(self, cpu: &mut Cpu<B>) -> u8 {
         let Mem(reg) = self;
         let addr = reg.read(cpu);
-        cpu.interconnect.read(addr)
+        cpu.read_mem(addr)
     }
 }
 
 impl Cpu {
     pub fn new(gb_type: GameboyType, interconnect: Interconnect) -> Cpu {
+        let boot = interconnect.boot_rom_mapped();
+        let reg = if boot {
+            Registers::power_on()
+        } else {
+            Registers::new(gb_type)
+        };
+        Cpu::with_registers(interconnect, reg, !boot)
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// A CPU in the middle of a program, at `reg.pc`
+    pub fn with_registers(bus: B, reg: Registers, ime: bool) -> Cpu<B> {
         Cpu {
-            reg: Registers::new(gb_type),
-            interconnect: interconnect,
-            ime: true,
+            reg: reg,
+            bus: bus,
+            ime: ime,
+            ime_pending: false,
             halted: false,
+            halt_bug: false,
+            lockup: None,
+            breakpoint: false,
+            cycles: 0,
         }
     }
 
-    pub fn step(&mut self) -> u32 {
-        let elapsed_cycles = {
-            self.handle_interrupt() + self.execute_instruction()
-        };
-        self.interconnect.cycle_flush(elapsed_cycles);
-        elapsed_cycles
+    pub fn status(&self) -> Status {
+        if let Some(lockup) = self.lockup {
+            Status::Locked(lockup)
+        } else if self.halted {
+            Status::Halted
+        } else {
+            Status::Running
+        }
+    }
+
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.lockup
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.reg
+    }
+
+    /// Whether LD B,B was executed since the last `clear_breakpoint`
+    pub fn breakpoint_hit(&self) -> bool {
+        self.breakpoint
+    }
+
+    pub fn clear_breakpoint(&mut self) {
+        self.breakpoint = false
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.reg
+    }
+
+    /// Interrupt master enable, and whether EI will set it after the next
+    /// instruction
+    pub fn ime(&self) -> (bool, bool) {
+        (self.ime, self.ime_pending)
     }
 
-    fn handle_interrupt(&mut self) -> u32 {
-        let ints = self.interconnect.int_flags & self.interconnect.int_enable;
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
 
-        if self.halted {
-            self.halted = ints == 0;
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn step(&mut self) -> u32 {
+        self.cycles = 0;
+
+        if self.lockup.is_some() {
+            // The CPU never recovers, but the rest of the system keeps running
+            let cycles = self.bus.cycles_until_event();
+            self.idle(cycles)
+        } else if self.halted {
+            self.step_halted()
+        } else {
+            let ime_pending = self.ime_pending;
+            self.handle_interrupt();
+            self.execute_instruction();
+
+            // EI only takes effect after the instruction following it
+            if ime_pending && self.ime_pending {
+                self.ime = true;
+                self.ime_pending = false
+            }
         }
+        self.cycles
+    }
 
-        if !self.ime {
-            return 0;
+    fn step_halted(&mut self) {
+        if self.pending_interrupts() != 0 {
+            // Any pending interrupt wakes the CPU up, regardless of IME
+            self.halted = false;
+            self.tick()
+        } else {
+            // Nothing can wake us up before the next event, so skip straight to it
+            let cycles = self.bus.cycles_until_event();
+            self.idle(cycles)
         }
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.bus.pending_interrupts()
+    }
 
-        if ints == 0 {
-            return 0;
+    fn handle_interrupt(&mut self) {
+        if !self.ime || self.pending_interrupts() == 0 {
+            return;
         }
 
         self.ime = false;
 
-        let int = ints.trailing_zeros();
-        let int_handler = {
+        // 2 wait states, 2 pushes and setting PC: 5 M-cycles
+        self.tick();
+        self.tick();
+
+        let pc = if self.halt_bug {
+            // HALT bug right after EI: the handler returns to the HALT itself
+            self.halt_bug = false;
+            self.reg.pc.wrapping_sub(1)
+        } else {
+            self.reg.pc
+        };
+
+        // The interrupt isn't selected until the high byte of PC has been
+        // pushed. If that push overwrites IE (SP = 0x0000) and no enabled
+        // interrupt remains, dispatch is cancelled and the CPU jumps to 0x0000.
+        self.push_u8((pc >> 8) as u8);
+
+        let ints = self.pending_interrupts();
+        let int_handler = if ints == 0 {
+            0x0000
+        } else {
+            let int = ints.trailing_zeros();
+            self.bus.acknowledge_interrupt(int);
             match int {
                 0 => 0x40,// VBLANK
                 1 => 0x48,// LCDC STATUS
@@ -220,17 +367,14 @@ impl Cpu {
             }
         };
 
-        self.interconnect.int_flags &= 0xff << (int + 1);
-
-        let pc = self.reg.pc;
-        self.push_u16(pc);
+        self.push_u8(pc as u8);
         self.reg.pc = int_handler;
-
-        20
+        self.tick()
     }
 
-    fn execute_instruction(&mut self) -> u32 {
-        let opcode = if !self.halted { self.fetch_u8() } else { 0 };
+    fn execute_instruction(&mut self) {
+        let start_cycles = self.cycles;
+        let opcode = self.fetch_u8();
 
         use super::registers::Reg8::*;
         use super::registers::Reg16::*;
@@ -302,7 +446,10 @@ impl Cpu {
                 0x3d => self.dec_8(A),
                 0x3e => self.ld(A, Imm8),
                 0x3f => self.ccf(),
-                0x40 => self.ld(B, B),
+                0x40 => {
+                    self.breakpoint = true;
+                    self.ld(B, B)
+                }
                 0x41 => self.ld(B, C),
                 0x42 => self.ld(B, D),
                 0x43 => self.ld(B, E),
@@ -484,7 +631,9 @@ impl Cpu {
                 0xfe => self.cp(Imm8),
                 0xff => self.rst(0x38),
 
-                _ => panic!("Invalid opcode: 0x{:x}\n{:#?}", opcode, self.reg),
+                0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+                    self.lock_up(opcode)
+                }
             }
         };
 
@@ -493,7 +642,14 @@ impl Cpu {
             Timing::Cond => OPCODE_COND_TIMES[opcode as usize] as u32,
             Timing::Cb(x) => x,
         };
-        cycles * 4
+
+        // Memory accesses have already ticked the rest of the system as they
+        // happened; whatever is left are internal cycles.
+        let elapsed = self.cycles - start_cycles;
+        let remaining = (cycles * 4).saturating_sub(elapsed);
+        if remaining > 0 {
+            self.idle(remaining)
+        }
     }
 
     fn execute_cb_instruction(&mut self) -> Timing {
@@ -779,7 +935,23 @@ impl Cpu {
     }
 
     fn halt(&mut self) -> Timing {
-        self.halted = true;
+        if !self.ime && self.pending_interrupts() != 0 {
+            // HALT bug: the CPU doesn't halt, and fails to increment PC
+            // when fetching the next opcode, so that byte is read twice.
+            self.halt_bug = true
+        } else {
+            self.halted = true
+        }
+        Timing::Default
+    }
+
+    fn lock_up(&mut self, opcode: u8) -> Timing {
+        let pc = self.reg.pc.wrapping_sub(1);
+        self.lockup = Some(Lockup {
+            opcode: opcode,
+            pc: pc,
+            bank: self.bus.rom_bank(pc),
+        });
         Timing::Default
     }
 
@@ -787,6 +959,7 @@ impl Cpu {
         let new_pc = src.read(self);
         if cond.is_true(self) {
             let ret = self.reg.pc;
+            self.tick();
             self.push_u16(ret);
             self.reg.pc = new_pc;
             Timing::Cond
@@ -796,6 +969,11 @@ impl Cpu {
     }
 
     fn ret(&mut self, cond: Cond) -> Timing {
+        match cond {
+            Cond::Uncond => {}
+            // Evaluating the condition takes an extra cycle
+            _ => self.tick(),
+        }
         if cond.is_true(self) {
             let new_pc = self.pop_u16();
             self.reg.pc = new_pc;
@@ -806,12 +984,14 @@ impl Cpu {
     }
 
     fn reti(&mut self) -> Timing {
-        self.ei();
+        // Unlike EI, RETI enables interrupts immediately
+        self.ime = true;
         self.ret(Cond::Uncond)
     }
 
     fn rst(&mut self, p: u8) -> Timing {
         let pc = self.reg.pc;
+        self.tick();
         self.push_u16(pc);
         self.reg.pc = p as u16;
         Timing::Default
@@ -848,9 +1028,7 @@ impl Cpu {
     fn jr<S: Src<u8>>(&mut self, cond: Cond, src: S) -> Timing {
         let offset = (src.read(self) as i8) as i16;
         if cond.is_true(self) {
-            let pc = self.reg.pc as i16;
-            let new_pc = (pc + offset) as u16;
-            self.reg.pc = new_pc;
+            self.reg.pc = self.reg.pc.wrapping_add(offset as u16);
             Timing::Cond
         } else {
             Timing::Default
@@ -1199,6 +1377,7 @@ impl Cpu {
 
     fn push<S: Src<u16>>(&mut self, src: S) -> Timing {
         let value = src.read(self);
+        self.tick();
         self.push_u16(value);
         Timing::Default
     }
@@ -1211,18 +1390,42 @@ impl Cpu {
 
     fn di(&mut self) -> Timing {
         self.ime = false;
+        self.ime_pending = false;
         Timing::Default
     }
 
     fn ei(&mut self) -> Timing {
-        self.ime = true;
+        self.ime_pending = true;
         Timing::Default
     }
 
+    fn tick(&mut self) {
+        self.idle(4)
+    }
+
+    fn idle(&mut self, cycles: u32) {
+        self.bus.tick(cycles);
+        self.cycles += cycles
+    }
+
+    fn read_mem(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.bus.read(addr)
+    }
+
+    fn write_mem(&mut self, addr: u16, val: u8) {
+        self.tick();
+        self.bus.write(addr, val)
+    }
+
     fn fetch_u8(&mut self) -> u8 {
         let pc = self.reg.pc;
-        let value = self.interconnect.read(pc);
-        self.reg.pc = pc.wrapping_add(1);
+        let value = self.read_mem(pc);
+        if self.halt_bug {
+            self.halt_bug = false
+        } else {
+            self.reg.pc = pc.wrapping_add(1)
+        }
         value
     }
 
@@ -1234,7 +1437,7 @@ impl Cpu {
 
     fn push_u8(&mut self, value: u8) {
         let sp = self.reg.sp.wrapping_sub(1);
-        self.interconnect.write(sp, value);
+        self.write_mem(sp, value);
         self.reg.sp = sp
     }
 
@@ -1245,7 +1448,7 @@ impl Cpu {
 
     fn pop_u8(&mut self) -> u8 {
         let sp = self.reg.sp;
-        let value = self.interconnect.read(sp);
+        let value = self.read_mem(sp);
         self.reg.sp = sp.wrapping_add(1);
         value
     }
@@ -1256,3 +1459,207 @@ impl Cpu {
         (high << 8) | low
     }
 }
+
+impl<B: Bus + SaveState> SaveState for Cpu<B> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"CPU ", |w| {
+            w.u16(self.reg.read_u16(Reg16::AF));
+            w.u16(self.reg.read_u16(Reg16::BC));
+            w.u16(self.reg.read_u16(Reg16::DE));
+            w.u16(self.reg.read_u16(Reg16::HL));
+            w.u16(self.reg.sp);
+            w.u16(self.reg.pc);
+            w.bool(self.ime);
+            w.bool(self.ime_pending);
+            w.bool(self.halted);
+            w.bool(self.halt_bug);
+            match self.lockup {
+                Some(lockup) => {
+                    w.bool(true);
+                    w.u8(lockup.opcode);
+                    w.u16(lockup.pc);
+                    // The bank is only used for reporting
+                    w.u32(lockup.bank.map_or(u32::MAX, |bank| bank as u32))
+                }
+                None => w.bool(false),
+            }
+        });
+        self.bus.save_state(writer)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"CPU ", |r| {
+            self.reg.write_u16(Reg16::AF, r.u16()?);
+            self.reg.write_u16(Reg16::BC, r.u16()?);
+            self.reg.write_u16(Reg16::DE, r.u16()?);
+            self.reg.write_u16(Reg16::HL, r.u16()?);
+            self.reg.sp = r.u16()?;
+            self.reg.pc = r.u16()?;
+            self.ime = r.bool()?;
+            self.ime_pending = r.bool()?;
+            self.halted = r.bool()?;
+            self.halt_bug = r.bool()?;
+            self.lockup = if r.bool()? {
+                let opcode = r.u8()?;
+                let pc = r.u16()?;
+                let bank = r.u32()?;
+                Some(Lockup {
+                    opcode: opcode,
+                    pc: pc,
+                    bank: if bank == u32::MAX { None } else { Some(bank as usize) },
+                })
+            } else {
+                None
+            };
+            Ok(())
+        })?;
+        self.bus.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cpu, Status};
+    use gbc::bus::Bus;
+    use gbc::registers::Registers;
+
+    const IF: usize = 0xff0f;
+    const IE: usize = 0xffff;
+    const EVENT_CYCLES: u32 = 64;
+
+    // Memory only, with IF and IE at their usual addresses. Halted CPUs skip
+    // EVENT_CYCLES at a time.
+    struct FlatBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for FlatBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.memory[addr as usize] = val
+        }
+
+        fn tick(&mut self, _cycles: u32) {}
+
+        fn pending_interrupts(&self) -> u8 {
+            self.memory[IF] & self.memory[IE] & 0x1f
+        }
+
+        fn acknowledge_interrupt(&mut self, int: u32) {
+            self.memory[IF] &= !(1 << int)
+        }
+
+        fn cycles_until_event(&self) -> u32 {
+            EVENT_CYCLES
+        }
+    }
+
+    // A CPU about to run `program` from `pc`, with the stack at 0xd000
+    fn cpu(pc: u16, program: &[u8]) -> Cpu<FlatBus> {
+        let mut memory = vec![0; 0x10000];
+        memory[pc as usize..pc as usize + program.len()].copy_from_slice(program);
+        let mut reg = Registers::default();
+        reg.pc = pc;
+        reg.sp = 0xd000;
+        Cpu::with_registers(FlatBus { memory: memory }, reg, false)
+    }
+
+    fn request(cpu: &mut Cpu<FlatBus>, interrupts: u8) {
+        cpu.bus_mut().memory[IF] = interrupts;
+        cpu.bus_mut().memory[IE] = interrupts
+    }
+
+    #[test]
+    fn jr_across_signed_boundaries() {
+        let cases = [(0x7ff0, 0x7f, 0x8071), (0x8010, 0x80, 0x7f92), (0xfff0, 0x7f, 0x0071),
+                     (0x0000, 0xfd, 0xffff)];
+        for &(pc, offset, target) in cases.iter() {
+            let mut cpu = cpu(pc, &[0x18, offset]);
+            cpu.step();
+            assert_eq!(cpu.registers().pc, target, "JR 0x{:02x} at 0x{:04x}", offset, pc);
+        }
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI; NOP; NOP with VBLANK already requested
+        let mut cpu = cpu(0x0300, &[0xfb, 0x00, 0x00]);
+        request(&mut cpu, 0x01);
+        cpu.step();
+        assert_eq!(cpu.ime(), (false, true));
+        cpu.step();
+        assert_eq!(cpu.registers().pc, 0x0302);
+        assert_eq!(cpu.ime(), (true, false));
+
+        // Dispatched before the second NOP, the NOP at 0x40 runs in the same step
+        cpu.step();
+        assert_eq!(cpu.registers().pc, 0x0041);
+        assert_eq!(&cpu.bus().memory[0xcffe..0xd000], &[0x02, 0x03]);
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_byte_twice() {
+        // HALT; INC A with IME clear and TIMER pending
+        let mut cpu = cpu(0x0200, &[0x76, 0x3c]);
+        request(&mut cpu, 0x04);
+        cpu.step();
+        assert_eq!(cpu.status(), Status::Running);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers().a, 2);
+        assert_eq!(cpu.registers().pc, 0x0202);
+        assert_eq!(cpu.bus().memory[IF], 0x04);
+    }
+
+    #[test]
+    fn halt_skips_to_the_next_event() {
+        // HALT; INC A, woken up by TIMER with IME clear
+        let mut cpu = cpu(0x0200, &[0x76, 0x3c]);
+        cpu.step();
+        assert_eq!(cpu.status(), Status::Halted);
+        assert_eq!(cpu.step(), EVENT_CYCLES);
+        assert_eq!(cpu.status(), Status::Halted);
+
+        request(&mut cpu, 0x04);
+        assert_eq!(cpu.step(), 4);
+        cpu.step();
+        assert_eq!(cpu.registers().a, 1);
+        assert_eq!(cpu.registers().pc, 0x0202);
+    }
+
+    #[test]
+    fn dispatch_takes_5_m_cycles_and_clears_one_request() {
+        // VBLANK and TIMER requested, VBLANK goes first
+        let mut cpu = cpu(0x0300, &[0x00]);
+        cpu.ime = true;
+        request(&mut cpu, 0x05);
+
+        // Dispatch and the NOP at 0x40
+        assert_eq!(cpu.step(), 20 + 4);
+        assert_eq!(cpu.registers().pc, 0x0041);
+        assert_eq!(cpu.bus().memory[IF], 0x04);
+    }
+
+    #[test]
+    fn ie_overwritten_by_the_push_cancels_dispatch() {
+        // With SP at 0x0000 the high byte of PC lands in IE. 0x02 disables
+        // the requested VBLANK, which stays requested, 0x01 keeps it enabled.
+        for &(pc, target, int_flags) in [(0x0200, 0x0000, 0x01), (0x0100, 0x0040, 0x00)].iter() {
+            let mut cpu = cpu(pc, &[0x00]);
+            cpu.ime = true;
+            cpu.registers_mut().sp = 0x0000;
+            request(&mut cpu, 0x01);
+
+            assert_eq!(cpu.step(), 20 + 4);
+            assert_eq!(cpu.registers().pc, target + 1, "PC 0x{:04x}", pc);
+            assert_eq!(cpu.bus().memory[IE], (pc >> 8) as u8);
+            assert_eq!(cpu.bus().memory[IF], int_flags);
+            assert_eq!(cpu.bus().memory[0xfffe], 0x00);
+            assert_eq!(cpu.ime(), (false, false));
+        }
+    }
+}
+
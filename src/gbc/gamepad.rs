@@ -1,6 +1,5 @@
 use super::Interrupt;
-
-use std::sync::mpsc::Receiver;
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
 #[derive(Debug)]
 pub enum ButtonState {
@@ -21,6 +20,25 @@ pub enum Button {
 }
 
 impl Button {
+    pub const ALL: [Button; 8] = [Button::Right,
+                                  Button::Left,
+                                  Button::Up,
+                                  Button::Down,
+                                  Button::A,
+                                  Button::B,
+                                  Button::Select,
+                                  Button::Start];
+
+    /// Bit of the button in a button mask: directions in the low nibble and
+    /// A, B, Select and Start in the high nibble, as in the joypad register
+    pub fn mask(self) -> u8 {
+        use self::Button::*;
+        match self {
+            Right | Left | Up | Down => self.flag(),
+            A | B | Select | Start => self.flag() << 4,
+        }
+    }
+
     fn flag(self) -> u8 {
         use self::Button::*;
         match self {
@@ -45,31 +63,33 @@ impl InputEvent {
             state: state,
         }
     }
+
+    /// Applies the event to a button mask
+    pub fn apply(&self, buttons: u8) -> u8 {
+        match self.state {
+            ButtonState::Down => buttons | self.button.mask(),
+            ButtonState::Up => buttons & !self.button.mask(),
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Gamepad {
     input_port_1: u8,
     input_port_2: u8,
     port: u8,
-    input: Receiver<InputEvent>,
 }
 
 impl Gamepad {
-    pub fn new(input: Receiver<InputEvent>) -> Gamepad {
+    pub fn new() -> Gamepad {
         Gamepad {
             input_port_1: 0x0f,
             input_port_2: 0x0f,
-            input: input,
             port: 0xf0,
         }
     }
 
     pub fn read(&mut self) -> u8 {
-
-        while let Ok(event) = self.input.try_recv() {
-            self.handle_event(event)
-        }
-
         let mut input = self.port | 0b1100_0000;
 
         if (self.port & 0x10) != 0 {
@@ -87,32 +107,38 @@ impl Gamepad {
         self.port = val & 0b0011_0000
     }
 
+    /// Sets the pressed buttons, as a mask of `Button::mask` bits
+    pub fn set_buttons(&mut self, buttons: u8) {
+        // The ports are active low
+        self.input_port_1 = !buttons & 0x0f;
+        self.input_port_2 = !buttons >> 4
+    }
+
+    /// Pressed buttons, as a mask of `Button::mask` bits
+    pub fn buttons(&self) -> u8 {
+        !(self.input_port_1 | self.input_port_2 << 4)
+    }
+
     pub fn cycle_flush(&mut self, cycle_count: u32) -> Option<Interrupt> {
         None
     }
+}
 
-    fn handle_event(&mut self, event: InputEvent) {
-        use self::Button::*;
+impl SaveState for Gamepad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"JOYP", |w| {
+            w.u8(self.input_port_1);
+            w.u8(self.input_port_2);
+            w.u8(self.port)
+        })
+    }
 
-        // println!("Handle event: {:?}", event);
-
-        match event.state {
-            ButtonState::Down => {
-                let mask = !event.button.flag();
-                match event.button {
-                    Up | Down | Left | Right => self.input_port_1 = self.input_port_1 & mask,
-                    A | B | Start | Select => self.input_port_2 = self.input_port_2 & mask,
-                }
-            }
-
-            ButtonState::Up => {
-                let flag = event.button.flag();
-                match event.button {
-                    Up | Down | Left | Right => self.input_port_1 = self.input_port_1 | flag,
-                    A | B | Start | Select => self.input_port_2 = self.input_port_2 | flag,
-                }
-
-            }
-        }
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"JOYP", |r| {
+            self.input_port_1 = r.u8()? & 0x0f;
+            self.input_port_2 = r.u8()? & 0x0f;
+            self.port = r.u8()? & 0xf0;
+            Ok(())
+        })
     }
 }
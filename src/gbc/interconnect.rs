@@ -1,24 +1,81 @@
 use super::ppu::Ppu;
 use super::spu::Spu;
 use super::cart::Cart;
+use super::boot_rom::BootRom;
 use super::timer::Timer;
 use super::gamepad::Gamepad;
 use super::GameboyType;
+use super::Interrupt;
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
+
+use std::cmp;
 
 const ZRAM_SIZE: usize = 0x7f;
 const RAM_SIZE: usize = 1024 * 32;
 
+const OAM_DMA_LENGTH: u16 = super::ppu::OAM_SIZE as u16;
+
+// Time to shift out a byte with the internal clock, at 8192 Hz or with the
+// CGB fast clock at 262144 Hz
+const SERIAL_TRANSFER_CYCLES: u32 = 8 * 512;
+const SERIAL_TRANSFER_CYCLES_FAST: u32 = 8 * 16;
+
+#[derive(Clone)]
+struct OamDma {
+    active: bool,
+    source: u16,
+    index: u16,
+    // Last byte read by the transfer, seen by the CPU on bus conflicts
+    value: u8,
+    // Transfer requested by a write to 0xff46, starts after a setup M-cycle
+    pending: Option<u16>,
+    setup: bool,
+}
+
+impl OamDma {
+    fn new() -> OamDma {
+        OamDma {
+            active: false,
+            source: 0,
+            index: 0,
+            value: 0xff,
+            pending: None,
+            setup: false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Interconnect {
     gameboy_type: GameboyType,
     cart: Cart,
+    boot_rom: Option<BootRom>,
+    // Kept after the boot ROM unmaps itself, so states saved while it ran
+    // can still be loaded
+    boot_rom_mapped: bool,
     ppu: Ppu,
     spu: Spu,
     timer: Timer,
     gamepad: Gamepad,
     ram: Box<[u8]>,
     zram: Box<[u8]>,
+    sb: u8,
+    sc: u8,
+    // Cycles left of the transfer in progress, 0 if there is none
+    serial_cycles: u32,
+    // Every byte sent while capturing, for test ROMs that report their
+    // results over serial
+    serial_output: Option<Vec<u8>>,
     svbk: u8,
+    key0: u8,
+    key1: u8,
+    rp: u8,
+    ff72: u8,
+    ff73: u8,
+    ff74: u8,
+    ff75: u8,
     ppu_dma: u8,
+    oam_dma: OamDma,
     pub int_enable: u8,
     pub int_flags: u8,
     ram_offset: usize,
@@ -27,142 +84,526 @@ pub struct Interconnect {
 impl Interconnect {
     pub fn new(gameboy_type: GameboyType,
                cart: Cart,
+               boot_rom: Option<BootRom>,
                ppu: Ppu,
                spu: Spu,
                gamepad: Gamepad)
                -> Interconnect {
+        let mut ppu = ppu;
+        if boot_rom.is_some() {
+            ppu.power_on()
+        }
         Interconnect {
             gameboy_type: gameboy_type,
             cart: cart,
+            boot_rom_mapped: boot_rom.is_some(),
+            boot_rom: boot_rom,
             ppu: ppu,
             spu: spu,
             timer: Timer::new(),
             gamepad: gamepad,
             ram: vec![0; RAM_SIZE].into_boxed_slice(),
             zram: vec![0; ZRAM_SIZE].into_boxed_slice(),
+            sb: 0,
+            sc: 0,
+            serial_cycles: 0,
+            serial_output: None,
             svbk: 0,
+            key0: 0,
+            key1: 0,
+            rp: 0,
+            ff72: 0,
+            ff73: 0,
+            ff74: 0,
+            ff75: 0,
             ppu_dma: 0,
+            oam_dma: OamDma::new(),
             int_enable: 0,
             int_flags: 0,
             ram_offset: 0,
         }
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        match addr {
-            0x0000...0x7fff => self.cart.read(addr),
-            0x8000...0x9fff => self.ppu.read(addr),
-            0xa000...0xbfff => self.cart.read_ram(addr),
-            0xc000...0xcfff => self.ram[(addr - 0xc000) as usize],
-            0xd000...0xdfff => self.ram[(addr - 0xc000) as usize + self.ram_offset],
-            0xe000...0xfdff => self.read(addr - 0xe000 + 0xc000),
+    pub fn cart(&self) -> &Cart {
+        &self.cart
+    }
 
-            0xff00 => self.gamepad.read(),
+    pub fn spu(&self) -> &Spu {
+        &self.spu
+    }
 
-            0xff01...0xff02 => {
-                // serial IO
-                0
-            }
-            0xff04...0xff07 => self.timer.read(addr),
+    pub fn spu_mut(&mut self) -> &mut Spu {
+        &mut self.spu
+    }
 
-            0xff10...0xff3f => self.spu.read(addr),
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
 
-            0xff0f => self.int_flags,
+    pub fn gamepad(&self) -> &Gamepad {
+        &self.gamepad
+    }
 
-            0xff46 => self.ppu_dma,
+    pub fn gamepad_mut(&mut self) -> &mut Gamepad {
+        &mut self.gamepad
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Bytes sent over the serial port since capturing was enabled
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial_output.as_ref().map_or(&[], |output| &output[..])
+    }
+
+    /// Keeps the bytes sent over the serial port. Output captured so far is
+    /// dropped when capturing is disabled.
+    pub fn set_serial_capture(&mut self, capture: bool) {
+        if !capture {
+            self.serial_output = None
+        } else if self.serial_output.is_none() {
+            self.serial_output = Some(Vec::new())
+        }
+    }
 
-            0xfe00...0xfeff | 0xff40...0xff45 | 0xff47...0xff4b | 0xff68...0xff69 | 0xff4f => {
-                self.ppu.read(addr)
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if self.oam_dma.active {
+            // The CPU can only reach HRAM and I/O during OAM DMA, reads from
+            // anywhere else see the byte currently being transferred.
+            match addr {
+                0x0000...0xfdff => return self.oam_dma.value,
+                0xfe00...0xfeff => return 0xff,
+                _ => {}
             }
+        }
+        self.bus_read(addr)
+    }
+
+    /// Reads like the CPU, but ignores OAM DMA and the PPU locking VRAM and
+    /// OAM, for debuggers and test conditions
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000...0x9fff | 0xfe00...0xfe9f => self.ppu.peek(addr),
+            _ => self.bus_read(addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        if self.oam_dma.active && addr < 0xff00 {
+            return;
+        }
+        self.bus_write(addr, val)
+    }
 
-            0xff4d => 0, // Speedswitch
-            0xff70 => self.svbk,
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x7fff => {
+                match self.boot_rom {
+                    Some(ref boot_rom) if self.boot_rom_mapped && boot_rom.contains(addr) => {
+                        boot_rom.read(addr)
+                    }
+                    _ => self.cart.read(addr),
+                }
+            }
+            0x8000...0x9fff => self.ppu.read(addr),
+            0xa000...0xbfff => self.cart.read_ram(addr),
+            0xc000...0xcfff => self.ram[(addr - 0xc000) as usize],
+            0xd000...0xdfff => self.ram[(addr - 0xc000) as usize + self.ram_offset],
+            0xe000...0xfdff => self.bus_read(addr - 0xe000 + 0xc000),
+            0xfe00...0xfe9f => self.ppu.read(addr),
+            0xfea0...0xfeff => {
+                // Unusable area, CGB (revision E) mirrors the upper nibble of the address
+                match self.gameboy_type {
+                    GameboyType::Cgb => ((addr & 0xf0) | ((addr & 0xf0) >> 4)) as u8,
+                    GameboyType::Dmg => 0x00,
+                }
+            }
+            0xff00...0xff7f => self.read_io(addr),
             0xff80...0xfffe => self.zram[(addr - 0xff80) as usize],
             0xffff => self.int_enable,
-            _ => panic!("Read: addr not in range: 0x{:x}", addr),
         }
     }
 
-    pub fn write(&mut self, addr: u16, val: u8) {
+    fn bus_write(&mut self, addr: u16, val: u8) {
         match addr {
             0x0000...0x7fff => self.cart.write(addr, val),
             0x8000...0x9fff => self.ppu.write(addr, val),
             0xa000...0xbfff => self.cart.write_ram(addr, val),
             0xc000...0xcfff => self.ram[(addr - 0xc000) as usize] = val,
             0xd000...0xdfff => self.ram[(addr - 0xc000) as usize + self.ram_offset] = val,
-            0xe000...0xfdff => self.write(addr - 0xe000 + 0xc000, val),
+            0xe000...0xfdff => self.bus_write(addr - 0xe000 + 0xc000, val),
+            0xfe00...0xfe9f => self.ppu.write(addr, val),
+            0xfea0...0xfeff => {} // Unusable area
+            0xff00...0xff7f => self.write_io(addr, val),
+            0xff80...0xfffe => self.zram[(addr - 0xff80) as usize] = val,
+            0xffff => self.int_enable = val,
+        }
+    }
 
-            0xff00 => self.gamepad.write(val),
+    // Unused bits read as 1, unmapped registers as 0xff
+    fn read_io(&mut self, addr: u16) -> u8 {
+        match addr {
+            0xff00 => self.gamepad.read(),
 
-            0xff01...0xff02 => {
-                // serial IO
-                if addr == 0xff01 {
-                    // print!("{}", val as char)
+            // serial IO
+            0xff01 => self.sb,
+            0xff02 => {
+                match self.gameboy_type {
+                    GameboyType::Cgb => 0x7c | self.sc,
+                    GameboyType::Dmg => 0x7e | self.sc,
                 }
             }
-            0xff04...0xff07 => self.timer.write(addr, val),
 
-            0xff10...0xff3f => self.spu.write(addr, val),
+            0xff04...0xff07 => self.timer.read(addr),
+            0xff0f => 0xe0 | self.int_flags,
+            0xff10...0xff3f => self.spu.read(addr),
+            0xff46 => self.ppu_dma,
+            0xff40...0xff4b => self.ppu.read(addr),
+            0xff50 => 0xff,
+
+            // Everything below is CGB only
+            0xff4c...0xff7f if !self.is_cgb() => 0xff,
+
+            0xff4c => self.key0,
+            0xff4d => 0x7e | self.key1, // Speedswitch
+            0xff4f | 0xff68...0xff6c => self.ppu.read(addr),
+            0xff56 => 0x3e | self.rp, // No infrared signal is ever received
+            0xff70 => 0xf8 | self.svbk,
+            0xff72 => self.ff72,
+            0xff73 => self.ff73,
+            0xff74 if (self.key0 & 0x04) == 0 => self.ff74,
+            0xff75 => 0x8f | self.ff75,
+            0xff76...0xff77 => 0x00, // PCM amplitudes, sound isn't emulated
+            _ => 0xff,
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff00 => self.gamepad.write(val),
 
-            0xff0f => self.int_flags = val,
+            // serial IO
+            0xff01 => self.sb = val,
+            0xff02 => {
+                self.sc = match self.gameboy_type {
+                    GameboyType::Cgb => val & 0x83,
+                    GameboyType::Dmg => val & 0x81,
+                };
+                self.start_serial_transfer()
+            }
 
+            0xff04...0xff07 => self.timer.write(addr, val),
+            0xff0f => self.int_flags = val & 0x1f,
+            0xff10...0xff3f => self.spu.write(addr, val),
             0xff46 => {
+                // A running transfer carries on until the new one has started
                 self.ppu_dma = val;
-                self.ppu_dma_transfer()
+                self.oam_dma.pending = Some((val as u16) << 8);
+                self.oam_dma.setup = true
             }
-
-            0xfe00...0xfeff | 0xff40...0xff45 | 0xff47...0xff4b | 0xff68...0xff69 | 0xff4f => {
-                self.ppu.write(addr, val)
+            0xff40...0xff4b => self.ppu.write(addr, val),
+            0xff50 => {
+                if (val & 0x01) != 0 {
+                    self.boot_rom_mapped = false
+                }
             }
 
-            0xff4d => {} // Speedswitch
+            // Everything below is CGB only
+            0xff4c...0xff7f if !self.is_cgb() => {}
+
+            0xff4c => {
+                // KEY0 can only be written by the CGB boot ROM, which uses it
+                // to put the hardware in DMG compatibility mode
+                if self.boot_rom_mapped {
+                    self.key0 = val;
+                    self.ppu.set_compatibility_mode((val & 0x04) != 0)
+                }
+            }
+            0xff4d => self.key1 = val & 0x01, // Speedswitch
+            0xff4f | 0xff68...0xff6c => self.ppu.write(addr, val),
+            0xff56 => self.rp = val & 0xc1,
             0xff70 => {
                 self.svbk = val & 0b111;
                 self.update_ram_offset()
             }
-
-            0xff7f => {} // TETRIS writes to this address for some reason
-
-            0xff80...0xfffe => self.zram[(addr - 0xff80) as usize] = val,
-            0xffff => self.int_enable = val,
-            _ => panic!("Write: addr not in range: 0x{:x} - val: 0x{:x}", addr, val),
+            0xff72 => self.ff72 = val,
+            0xff73 => self.ff73 = val,
+            0xff74 if (self.key0 & 0x04) == 0 => self.ff74 = val,
+            0xff75 => self.ff75 = val & 0x70,
+            _ => {}
         }
     }
 
     pub fn cycle_flush(&mut self, cycle_count: u32) {
 
+        if self.oam_dma.active || self.oam_dma.pending.is_some() {
+            for _ in 0..cycle_count / 4 {
+                self.oam_dma_cycle()
+            }
+        }
+
         self.int_flags |= self.ppu.cycle_flush(cycle_count);
 
+        if self.serial_cycles > 0 {
+            self.serial_cycle_flush(cycle_count)
+        }
+
         if let Some(int) = self.timer.cycle_flush(cycle_count) {
             self.int_flags |= int.flag();
         }
 
+        self.spu.cycle_flush(cycle_count);
+
         if let Some(int) = self.gamepad.cycle_flush(cycle_count) {
             self.int_flags |= int.flag();
         }
 
     }
 
-    fn ppu_dma_transfer(&mut self) {
-        let dma_start = (self.ppu_dma as u16) << 8;
-        let dma_end = dma_start | 0x009f;
+    /// ROM bank mapped at `addr`, if `addr` is in ROM
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x0000...0x7fff => Some(self.cart.rom_bank(addr)),
+            _ => None,
+        }
+    }
 
-        // if dma_start > 0x7fff && dma_end < 0xc000 {
-        // panic!("Illegal DMA address range: 0x{:x} - 0x{:x}",
-        // dma_start,
-        // dma_end);
-        // }
+    /// Number of cycles until the next event that may raise an interrupt.
+    /// Always a non-zero multiple of 4.
+    pub fn cycles_until_event(&self) -> u32 {
+        let cycles = cmp::min(self.ppu.cycles_until_event(),
+                              self.timer.cycles_until_event());
+        let cycles = match self.serial_cycles {
+            0 => cycles,
+            serial_cycles => cmp::min(cycles, serial_cycles),
+        };
+        cmp::max(4, (cycles + 3) & !3)
+    }
 
-        let mut oam = vec![0; super::ppu::OAM_SIZE].into_boxed_slice();
+    // Nothing is ever connected, so only transfers with the internal clock
+    // complete, and 0xff is shifted in
+    fn start_serial_transfer(&mut self) {
+        if (self.sc & 0x81) == 0x81 {
+            if let Some(ref mut output) = self.serial_output {
+                output.push(self.sb)
+            }
+            self.serial_cycles = if (self.sc & 0x02) != 0 {
+                SERIAL_TRANSFER_CYCLES_FAST
+            } else {
+                SERIAL_TRANSFER_CYCLES
+            }
+        } else {
+            self.serial_cycles = 0
+        }
+    }
+
+    fn serial_cycle_flush(&mut self, cycle_count: u32) {
+        if cycle_count < self.serial_cycles {
+            self.serial_cycles -= cycle_count;
+            return;
+        }
+        self.serial_cycles = 0;
+        self.sb = 0xff;
+        self.sc &= 0x7f;
+        self.int_flags |= Interrupt::Serial.flag()
+    }
+
+    // Transfers one byte to OAM per M-cycle
+    fn oam_dma_cycle(&mut self) {
+        if let Some(source) = self.oam_dma.pending {
+            if self.oam_dma.setup {
+                self.oam_dma.setup = false
+            } else {
+                self.oam_dma.pending = None;
+                self.oam_dma.active = true;
+                self.oam_dma.source = source;
+                self.oam_dma.index = 0;
+                self.ppu.set_oam_dma_active(true)
+            }
+        }
 
-        for a in dma_start..dma_end {
-            oam[(a - dma_start) as usize] = self.read(a)
+        if self.oam_dma.active {
+            let addr = self.oam_dma.source + self.oam_dma.index;
+            // Sources above 0xdfff read from work RAM instead
+            let addr = if addr >= 0xe000 { addr - 0x2000 } else { addr };
+            let val = self.bus_read(addr);
+
+            self.oam_dma.value = val;
+            self.ppu.oam_dma_write(self.oam_dma.index as usize, val);
+            self.oam_dma.index += 1;
+
+            if self.oam_dma.index == OAM_DMA_LENGTH {
+                self.oam_dma.active = false;
+                self.ppu.set_oam_dma_active(false)
+            }
         }
+    }
 
-        self.ppu.oam_dma_transfer(oam)
+    fn is_cgb(&self) -> bool {
+        match self.gameboy_type {
+            GameboyType::Cgb => true,
+            GameboyType::Dmg => false,
+        }
     }
 
     fn update_ram_offset(&mut self) {
-        self.ram_offset = self.svbk as usize * 0x1000
+        // Bank 0 can't be mapped at 0xd000, selecting it maps bank 1 instead
+        let bank = cmp::max(self.svbk, 1) as usize;
+        self.ram_offset = (bank - 1) * 0x1000
+    }
+}
+
+impl SaveState for Interconnect {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"BUS ", |w| {
+            w.bool(self.boot_rom_mapped);
+            w.bytes(&self.ram);
+            w.bytes(&self.zram);
+            w.u8(self.sb);
+            w.u8(self.sc);
+            w.u32(self.serial_cycles);
+            w.u8(self.svbk);
+            w.u8(self.key0);
+            w.u8(self.key1);
+            w.u8(self.rp);
+            w.u8(self.ff72);
+            w.u8(self.ff73);
+            w.u8(self.ff74);
+            w.u8(self.ff75);
+            w.u8(self.ppu_dma);
+            w.bool(self.oam_dma.active);
+            w.u16(self.oam_dma.source);
+            w.u16(self.oam_dma.index);
+            w.u8(self.oam_dma.value);
+            w.bool(self.oam_dma.pending.is_some());
+            w.u16(self.oam_dma.pending.unwrap_or(0));
+            w.bool(self.oam_dma.setup);
+            w.u8(self.int_enable);
+            w.u8(self.int_flags);
+        });
+        self.cart.save_state(writer);
+        self.ppu.save_state(writer);
+        self.spu.save_state(writer);
+        self.timer.save_state(writer);
+        self.gamepad.save_state(writer)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"BUS ", |r| {
+            self.boot_rom_mapped = r.bool()?;
+            if self.boot_rom_mapped && self.boot_rom.is_none() {
+                return Err(StateError::BootRomMissing);
+            }
+            r.bytes_into(&mut self.ram)?;
+            r.bytes_into(&mut self.zram)?;
+            self.sb = r.u8()?;
+            self.sc = r.u8()?;
+            self.serial_cycles = if r.version() >= 2 { r.u32()? } else { 0 };
+            self.svbk = r.u8()? & 0b111;
+            self.key0 = r.u8()?;
+            self.key1 = r.u8()?;
+            self.rp = r.u8()?;
+            self.ff72 = r.u8()?;
+            self.ff73 = r.u8()?;
+            self.ff74 = r.u8()?;
+            self.ff75 = r.u8()?;
+            self.ppu_dma = r.u8()?;
+            self.oam_dma.active = r.bool()?;
+            self.oam_dma.source = r.u16()?;
+            if self.oam_dma.source & 0xff != 0 {
+                return Err(StateError::Invalid("OAM DMA source"));
+            }
+            self.oam_dma.index = r.u16()?;
+            // A finished transfer stops at OAM_DMA_LENGTH, a running one before it
+            let index_limit = if self.oam_dma.active {
+                OAM_DMA_LENGTH - 1
+            } else {
+                OAM_DMA_LENGTH
+            };
+            if self.oam_dma.index > index_limit {
+                return Err(StateError::Invalid("OAM DMA index"));
+            }
+            self.oam_dma.value = r.u8()?;
+            let pending = r.bool()?;
+            let source = r.u16()?;
+            if pending && source & 0xff != 0 {
+                return Err(StateError::Invalid("OAM DMA source"));
+            }
+            self.oam_dma.pending = if pending { Some(source) } else { None };
+            self.oam_dma.setup = r.bool()?;
+            self.int_enable = r.u8()?;
+            self.int_flags = r.u8()? & 0x1f;
+            self.update_ram_offset();
+            Ok(())
+        })?;
+        self.cart.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.spu.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.gamepad.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interconnect;
+    use gbc::GameboyType;
+    use gbc::cart::Cart;
+    use gbc::gamepad::Gamepad;
+    use gbc::ppu::Ppu;
+    use gbc::spu::Spu;
+    use fixtures::test_rom;
+
+    // With the LCD off, so only OAM DMA locks OAM. 0xc000-0xc09f holds
+    // 0x5a ^ offset.
+    fn interconnect() -> Interconnect {
+        let cart = Cart::new(test_rom(0)).unwrap();
+        let ppu = Ppu::new(GameboyType::Dmg);
+        let mut interconnect = Interconnect::new(GameboyType::Dmg,
+                                                 cart,
+                                                 None,
+                                                 ppu,
+                                                 Spu::new(),
+                                                 Gamepad::new());
+        interconnect.write(0xff40, 0x00);
+        for i in 0..0xa0 {
+            interconnect.write(0xc000 + i, 0x5a ^ i as u8)
+        }
+        interconnect
+    }
+
+    #[test]
+    fn oam_dma_takes_160_m_cycles() {
+        let mut interconnect = interconnect();
+        interconnect.write(0xff80, 0x42);
+        interconnect.write(0xff46, 0xc0);
+
+        // A setup M-cycle, then one byte per M-cycle. Until the last one the
+        // CPU only reaches HRAM and I/O, and reads the byte just transferred.
+        interconnect.cycle_flush(4 * 160);
+        assert_eq!(interconnect.read(0xfe00), 0xff);
+        assert_eq!(interconnect.read(0xc000), 0x5a ^ 0x9e);
+        assert_eq!(interconnect.read(0x0150), 0x5a ^ 0x9e);
+        assert_eq!(interconnect.read(0xff80), 0x42);
+        interconnect.write(0xc000, 0x00);
+
+        interconnect.cycle_flush(4);
+        for i in 0..0xa0 {
+            assert_eq!(interconnect.read(0xfe00 + i), 0x5a ^ i as u8, "OAM 0x{:02x}", i)
+        }
+        assert_eq!(interconnect.read(0xc000), 0x5a);
+    }
+
+    #[test]
+    fn oam_dma_above_0xdfff_reads_work_ram() {
+        let mut interconnect = interconnect();
+        interconnect.write(0xff46, 0xe0);
+        interconnect.cycle_flush(4 * 161);
+        assert_eq!(interconnect.read(0xfe9f), 0x5a ^ 0x9f);
     }
 }
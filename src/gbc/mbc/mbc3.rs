@@ -1,5 +1,6 @@
 use super::Mbc;
 use super::MbcInfo;
+use super::super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
 #[derive(Debug,Copy,Clone)]
 struct Rtc {
@@ -10,7 +11,26 @@ struct Rtc {
     rtc_days_high: u8,
 }
 
-#[derive(Debug)]
+impl SaveState for Rtc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.rtc_seconds);
+        writer.u8(self.rtc_minutes);
+        writer.u8(self.rtc_hours);
+        writer.u8(self.rtc_days_low);
+        writer.u8(self.rtc_days_high)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.rtc_seconds = reader.u8()?;
+        self.rtc_minutes = reader.u8()?;
+        self.rtc_hours = reader.u8()?;
+        self.rtc_days_low = reader.u8()?;
+        self.rtc_days_high = reader.u8()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug,Clone)]
 pub struct Mbc3 {
     ram_write_protected: bool,
     rom_bank: u8,
@@ -64,7 +84,7 @@ impl Mbc3 {
 }
 
 impl Mbc for Mbc3 {
-    fn read(&self, rom: &Box<[u8]>, addr: u16) -> u8 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
         match addr {
             0x0000...0x3fff => rom[addr as usize],
             0x4000...0x7fff => rom[addr as usize - 0x4000 + self.rom_offset],
@@ -89,6 +109,10 @@ impl Mbc for Mbc3 {
         self.update_ram_offset()
     }
 
+    fn rom_bank(&self) -> usize {
+        self.rom_offset / 0x4000
+    }
+
     fn read_ram(&self, addr: u16) -> u8 {
         if !self.ram_write_protected {
             match self.ram_bank {
@@ -118,4 +142,33 @@ impl Mbc for Mbc3 {
             }
         }
     }
+
+    fn clone_box(&self) -> Box<Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl SaveState for Mbc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_write_protected);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.u8(self.rtc_latch);
+        self.rtc.save_state(writer);
+        self.latched_rtc.save_state(writer);
+        writer.bytes(&self.ram)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_write_protected = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        self.rtc_latch = reader.u8()?;
+        self.rtc.load_state(reader)?;
+        self.latched_rtc.load_state(reader)?;
+        reader.bytes_into(&mut self.ram)?;
+        self.update_rom_offset();
+        self.update_ram_offset();
+        Ok(())
+    }
 }
@@ -1,7 +1,8 @@
 use super::Mbc;
 use super::MbcInfo;
+use super::super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub struct Mbc5 {
     ram_write_protected: bool,
     rom_bank_0: u8,
@@ -44,7 +45,7 @@ impl Mbc5 {
 }
 
 impl Mbc for Mbc5 {
-    fn read(&self, rom: &Box<[u8]>, addr: u16) -> u8 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
         match addr {
             0x0000...0x3fff => rom[addr as usize],
             0x4000...0x7fff => rom[addr as usize - 0x4000 + self.rom_offset],
@@ -65,6 +66,10 @@ impl Mbc for Mbc5 {
         self.update_ram_offset()
     }
 
+    fn rom_bank(&self) -> usize {
+        self.rom_offset / 0x4000
+    }
+
     fn read_ram(&self, addr: u16) -> u8 {
         if !self.ram_write_protected {
             self.ram[addr as usize - 0xa000 + self.ram_offset]
@@ -78,4 +83,29 @@ impl Mbc for Mbc5 {
             self.ram[addr as usize - 0xa000 + self.ram_offset] = val
         }
     }
+
+    fn clone_box(&self) -> Box<Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl SaveState for Mbc5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_write_protected);
+        writer.u8(self.rom_bank_0);
+        writer.u8(self.rom_bank_1);
+        writer.u8(self.ram_bank);
+        writer.bytes(&self.ram)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_write_protected = reader.bool()?;
+        self.rom_bank_0 = reader.u8()?;
+        self.rom_bank_1 = reader.u8()?;
+        self.ram_bank = reader.u8()?;
+        reader.bytes_into(&mut self.ram)?;
+        self.update_rom_offset();
+        self.update_ram_offset();
+        Ok(())
+    }
 }
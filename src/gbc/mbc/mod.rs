@@ -6,18 +6,16 @@ use self::mbc1::Mbc1;
 use self::mbc3::Mbc3;
 use self::mbc5::Mbc5;
 
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
+
 #[derive(Debug)]
 pub struct RamInfo {
     size: u32,
-    bank_count: u32,
 }
 
 impl RamInfo {
-    pub fn new(size: u32, bank_count: u32) -> RamInfo {
-        RamInfo {
-            size: size,
-            bank_count: bank_count,
-        }
+    pub fn new(size: u32) -> RamInfo {
+        RamInfo { size: size }
     }
 }
 
@@ -25,15 +23,13 @@ impl RamInfo {
 pub struct MbcInfo {
     mbc_type: MbcType,
     ram_info: Option<RamInfo>,
-    has_batt: bool,
 }
 
 impl MbcInfo {
-    pub fn new(mbc_type: MbcType, ram_info: Option<RamInfo>, has_batt: bool) -> MbcInfo {
+    pub fn new(mbc_type: MbcType, ram_info: Option<RamInfo>) -> MbcInfo {
         MbcInfo {
             mbc_type: mbc_type,
             ram_info: ram_info,
-            has_batt: has_batt,
         }
     }
 }
@@ -47,11 +43,15 @@ pub enum MbcType {
     Mbc5,
 }
 
-pub trait Mbc {
-    fn read(&self, rom: &Box<[u8]>, addr: u16) -> u8;
+/// Banking registers, RAM and RTC are part of save states
+pub trait Mbc: SaveState {
+    fn read(&self, rom: &[u8], addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
+    /// Bank currently mapped at 0x4000-0x7fff
+    fn rom_bank(&self) -> usize;
     fn read_ram(&self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, val: u8);
+    fn clone_box(&self) -> Box<Mbc>;
 }
 
 pub fn new_mbc(mbc_info: MbcInfo) -> Box<Mbc> {
@@ -64,16 +64,21 @@ pub fn new_mbc(mbc_info: MbcInfo) -> Box<Mbc> {
     }
 }
 
+#[derive(Clone)]
 struct RomOnly;
 
 impl Mbc for RomOnly {
-    fn read(&self, rom: &Box<[u8]>, addr: u16) -> u8 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
         rom[addr as usize]
     }
 
     #[allow(unused_variables)]
     fn write(&mut self, addr: u16, val: u8) {}
 
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
     #[allow(unused_variables)]
     fn read_ram(&self, addr: u16) -> u8 {
         0
@@ -81,4 +86,18 @@ impl Mbc for RomOnly {
 
     #[allow(unused_variables)]
     fn write_ram(&mut self, addr: u16, val: u8) {}
+
+    fn clone_box(&self) -> Box<Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl SaveState for RomOnly {
+    #[allow(unused_variables)]
+    fn save_state(&self, writer: &mut StateWriter) {}
+
+    #[allow(unused_variables)]
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
 }
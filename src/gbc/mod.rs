@@ -1,12 +1,16 @@
+pub mod boot_rom;
+pub mod bus;
 pub mod cart;
 pub mod cpu;
 pub mod ppu;
 pub mod spu;
 pub mod interconnect;
 pub mod gamepad;
+pub mod palette;
+pub mod save_state;
+pub mod registers;
 
 mod disassembler;
-mod registers;
 mod opcode;
 mod timer;
 mod mbc;
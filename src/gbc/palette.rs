@@ -0,0 +1,267 @@
+use super::gamepad::Button;
+
+use std::fmt;
+
+/// Four shades from lightest to darkest, as 0xRRGGBB
+pub type Shades = [u32; 4];
+
+/// Colours used to display DMG games. Background and both object palettes
+/// can differ, like in the CGB compatibility palettes.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct DmgPalette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Preset {
+    PocketGrey,
+    OriginalGreen,
+    HighContrast,
+}
+
+const DEFAULT: Shades = [0xe0f8d0, 0x88c070, 0x275046, 0x081820];
+const POCKET_GREY: Shades = [0xc4cfa1, 0x8b956d, 0x4d533c, 0x1f1f1f];
+const ORIGINAL_GREEN: Shades = [0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f];
+const HIGH_CONTRAST: Shades = [0xffffff, 0xaaaaaa, 0x555555, 0x000000];
+
+impl DmgPalette {
+    /// Same shades for background and objects
+    pub fn uniform(shades: Shades) -> DmgPalette {
+        DmgPalette {
+            bg: shades,
+            obj0: shades,
+            obj1: shades,
+        }
+    }
+
+    pub fn preset(preset: Preset) -> DmgPalette {
+        DmgPalette::uniform(match preset {
+            Preset::PocketGrey => POCKET_GREY,
+            Preset::OriginalGreen => ORIGINAL_GREEN,
+            Preset::HighContrast => HIGH_CONTRAST,
+        })
+    }
+
+    /// Evenly spaced greys, the shades reference screenshots of test ROMs
+    /// like dmg-acid2 and the mealybug tearoom tests are made with
+    pub fn canonical() -> DmgPalette {
+        DmgPalette::uniform(HIGH_CONTRAST)
+    }
+
+    /// The palette the CGB boot ROM picks when `direction`, optionally with
+    /// A or B, is held while the logo is shown
+    pub fn cgb_compatibility(direction: Button, button: Option<Button>) -> Option<DmgPalette> {
+        use self::Button::*;
+
+        let split = |bg, obj0, obj1| {
+            DmgPalette {
+                bg: bg,
+                obj0: obj0,
+                obj1: obj1,
+            }
+        };
+
+        let brown = [0xffffff, 0xffad63, 0x843100, 0x000000];
+        let red = [0xffffff, 0xff8484, 0x943a3a, 0x000000];
+        let green = [0xffffff, 0x7bff31, 0x008400, 0x000000];
+        let blue = [0xffffff, 0x63a5ff, 0x0000ff, 0x000000];
+
+        let palette = match (direction, button) {
+            (Up, None) => DmgPalette::uniform(brown),
+            (Up, Some(A)) => split(red, green, blue),
+            (Up, Some(B)) => DmgPalette::uniform([0xffe6c5, 0xce9c84, 0x846b29, 0x5a3108]),
+            (Left, None) => split([0xffffff, 0x65a49b, 0x0000fe, 0x000000], red, red),
+            (Left, Some(A)) => split([0xffffff, 0x8c8cde, 0x52528c, 0x000000], red, brown),
+            (Left, Some(B)) => DmgPalette::uniform([0xffffff, 0xa5a5a5, 0x525252, 0x000000]),
+            (Down, None) => DmgPalette::uniform([0xffffa5, 0xff9494, 0x9494ff, 0x000000]),
+            (Down, Some(A)) => DmgPalette::uniform([0xffffff, 0xffff00, 0xff0000, 0x000000]),
+            (Down, Some(B)) => split([0xffffff, 0xffff00, 0x7b4a00, 0x000000], blue, green),
+            (Right, None) => DmgPalette::uniform([0xffffff, 0x52ff00, 0xff4200, 0x000000]),
+            (Right, Some(A)) => split([0xffffff, 0x7bff31, 0x0063c5, 0x000000], red, red),
+            (Right, Some(B)) => DmgPalette::uniform([0x000000, 0x008484, 0xffde00, 0xffffff]),
+            _ => return None,
+        };
+        Some(palette)
+    }
+
+    /// Parses a palette config file. Each line sets `preset`, `shades` (all
+    /// three palettes), `bg`, `obj0` or `obj1`, later lines override earlier
+    /// ones:
+    ///
+    /// ```text
+    /// # Greyscale background, green objects
+    /// preset = high_contrast
+    /// obj0 = 9bbc0f 8bac0f 306230 0f380f
+    /// obj1 = #9bbc0f #8bac0f #306230 #0f380f
+    /// ```
+    pub fn from_config(config: &str) -> Result<DmgPalette, ConfigError> {
+        let mut palette = DmgPalette::default();
+
+        for (index, line) in config.lines().enumerate() {
+            let line_num = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(ConfigError::Syntax(line_num)),
+            };
+
+            match key {
+                "preset" => {
+                    let preset = match value {
+                        "pocket_grey" => Preset::PocketGrey,
+                        "original_green" => Preset::OriginalGreen,
+                        "high_contrast" => Preset::HighContrast,
+                        _ => return Err(ConfigError::UnknownPreset(line_num, value.to_string())),
+                    };
+                    palette = DmgPalette::preset(preset)
+                }
+                "shades" => palette = DmgPalette::uniform(parse_shades(line_num, value)?),
+                "bg" => palette.bg = parse_shades(line_num, value)?,
+                "obj0" => palette.obj0 = parse_shades(line_num, value)?,
+                "obj1" => palette.obj1 = parse_shades(line_num, value)?,
+                _ => return Err(ConfigError::UnknownKey(line_num, key.to_string())),
+            }
+        }
+        Ok(palette)
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> DmgPalette {
+        DmgPalette::uniform(DEFAULT)
+    }
+}
+
+// A comment starts at a # that isn't part of a #RRGGBB colour
+fn strip_comment(line: &str) -> &str {
+    for (index, _) in line.match_indices('#') {
+        let rest = &line[index + 1..];
+        let hex_digits = rest.chars().take_while(|c| c.is_digit(16)).count();
+        let colour_end = rest[hex_digits..].chars().next().map_or(true, char::is_whitespace);
+        if hex_digits != 6 || !colour_end {
+            return &line[..index];
+        }
+    }
+    line
+}
+
+fn parse_shades(line_num: usize, value: &str) -> Result<Shades, ConfigError> {
+    let colors: Vec<&str> = value.split_whitespace().collect();
+    if colors.len() != 4 {
+        return Err(ConfigError::ShadeCount(line_num, colors.len()));
+    }
+
+    let mut shades = [0; 4];
+    for (shade, color) in shades.iter_mut().zip(colors) {
+        let hex = color.trim_start_matches('#');
+        *shade = match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => rgb,
+            _ => return Err(ConfigError::InvalidColor(line_num, color.to_string())),
+        }
+    }
+    Ok(shades)
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Syntax(usize),
+    UnknownKey(usize, String),
+    UnknownPreset(usize, String),
+    ShadeCount(usize, usize),
+    InvalidColor(usize, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Syntax(line) => write!(f, "line {}: expected key = value", line),
+            ConfigError::UnknownKey(line, ref key) => write!(f, "line {}: unknown key {}", line, key),
+            ConfigError::UnknownPreset(line, ref preset) => {
+                write!(f, "line {}: unknown preset {}", line, preset)
+            }
+            ConfigError::ShadeCount(line, count) => {
+                write!(f, "line {}: expected 4 colours, got {}", line, count)
+            }
+            ConfigError::InvalidColor(line, ref color) => {
+                write!(f, "line {}: invalid colour {}, expected RRGGBB", line, color)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DmgPalette, ConfigError, Preset, POCKET_GREY, HIGH_CONTRAST};
+
+    #[test]
+    fn valid_config() {
+        let config = "# Greyscale background, green objects\n\
+                      preset = high_contrast\n\
+                      \n\
+                      obj0 = 9bbc0f 8bac0f 306230 0f380f  # trailing comment\n\
+                      obj1=#9bbc0f #8bac0f #306230 #0f380f\n";
+        let green = [0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f];
+        assert_eq!(DmgPalette::from_config(config).unwrap(),
+                   DmgPalette {
+                       bg: HIGH_CONTRAST,
+                       obj0: green,
+                       obj1: green,
+                   });
+    }
+
+    #[test]
+    fn partial_config() {
+        assert_eq!(DmgPalette::from_config("").unwrap(), DmgPalette::default());
+
+        // Later lines override earlier ones, unset palettes keep the default
+        let palette = DmgPalette::from_config("bg = 000000 111111 222222 333333\n\
+                                               preset = pocket_grey\n\
+                                               obj1 = ffffff aaaaaa 555555 000000")
+            .unwrap();
+        assert_eq!(palette.bg, POCKET_GREY);
+        assert_eq!(palette.obj0, POCKET_GREY);
+        assert_eq!(palette.obj1, HIGH_CONTRAST);
+
+        let palette = DmgPalette::from_config("obj0 = ffffff aaaaaa 555555 000000").unwrap();
+        assert_eq!(palette.bg, DmgPalette::default().bg);
+        assert_eq!(palette.obj0, DmgPalette::preset(Preset::HighContrast).obj0);
+    }
+
+    #[test]
+    fn malformed_config() {
+        let error = |config| DmgPalette::from_config(config).unwrap_err();
+
+        match error("# comment\nshades") {
+            ConfigError::Syntax(2) => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        match error("colour = ffffff aaaaaa 555555 000000") {
+            ConfigError::UnknownKey(1, ref key) if key == "colour" => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        match error("preset = sepia") {
+            ConfigError::UnknownPreset(1, ref preset) if preset == "sepia" => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        match error("\nbg = ffffff aaaaaa 555555") {
+            ConfigError::ShadeCount(2, 3) => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        match error("obj1 = ffffff aaaaaa 55555g 000000") {
+            ConfigError::InvalidColor(1, ref colour) if colour == "55555g" => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+        match error("obj1 = ffffff aaaaaa fff 000000") {
+            ConfigError::InvalidColor(1, ref colour) if colour == "fff" => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
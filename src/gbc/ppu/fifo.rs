@@ -0,0 +1,437 @@
+// Dot based renderer, modelled on the background fetcher and pixel FIFOs of
+// the real PPU. Mode 3 ends when all 160 pixels have been shifted out, so its
+// length depends on SCX, the window and sprites.
+
+use std::collections::VecDeque;
+
+use super::{Ppu, DISPLAY_WIDTH, SPRITE_COUNT};
+use super::super::save_state::{SaveState, StateWriter, StateReader, StateError};
+
+// The first tile fetched on each line is thrown away
+const FIRST_FETCH_DOTS: u32 = 6;
+const SPRITE_FETCH_DOTS: u32 = 6;
+
+// Fetcher steps 0-5 read the tile number and data, 6 waits for an empty FIFO
+const PUSH_STEP: u8 = 6;
+
+#[derive(Clone,Copy)]
+struct Pixel {
+    color: u8,
+    attributes: u8,
+    // OAM index of sprite pixels
+    sprite: usize,
+}
+
+const TRANSPARENT: Pixel = Pixel {
+    color: 0,
+    attributes: 0,
+    sprite: 0,
+};
+
+#[derive(Clone)]
+pub struct PixelFifo {
+    bg: VecDeque<Pixel>,
+    obj: VecDeque<Pixel>,
+    fetch_step: u8,
+    fetch_x: u8,
+    tile_attributes: u8,
+    tile_address: u16,
+    data_low: u8,
+    data_high: u8,
+    window: bool,
+    discard: u8,
+    lx: u8,
+    dots: u32,
+    delay: u32,
+    sprites: VecDeque<usize>,
+    sprite_dots: u32,
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetch_step: 0,
+            fetch_x: 0,
+            tile_attributes: 0,
+            tile_address: 0x8000,
+            data_low: 0,
+            data_high: 0,
+            window: false,
+            discard: 0,
+            lx: DISPLAY_WIDTH as u8,
+            dots: 0,
+            delay: 0,
+            sprites: VecDeque::with_capacity(40),
+            sprite_dots: 0,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.lx as usize == DISPLAY_WIDTH
+    }
+
+    pub fn window_drawn(&self) -> bool {
+        self.window
+    }
+
+    /// Length of mode 3 so far
+    pub fn dots(&self) -> u32 {
+        self.dots
+    }
+}
+
+impl Pixel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.color);
+        writer.u8(self.attributes);
+        writer.u8(self.sprite as u8)
+    }
+
+    fn load_state(reader: &mut StateReader) -> Result<Pixel, StateError> {
+        Ok(Pixel {
+            color: reader.u8()? & 0x03,
+            attributes: reader.u8()?,
+            sprite: sprite_index(reader)?,
+        })
+    }
+}
+
+fn sprite_index(reader: &mut StateReader) -> Result<usize, StateError> {
+    match reader.u8()? as usize {
+        sprite if sprite < SPRITE_COUNT => Ok(sprite),
+        _ => Err(StateError::Invalid("sprite index")),
+    }
+}
+
+fn save_pixels(pixels: &VecDeque<Pixel>, writer: &mut StateWriter) {
+    writer.u8(pixels.len() as u8);
+    for pixel in pixels.iter() {
+        pixel.save_state(writer)
+    }
+}
+
+fn load_pixels(pixels: &mut VecDeque<Pixel>, reader: &mut StateReader) -> Result<(), StateError> {
+    pixels.clear();
+    for _ in 0..reader.u8()? {
+        pixels.push_back(Pixel::load_state(reader)?)
+    }
+    Ok(())
+}
+
+impl SaveState for PixelFifo {
+    fn save_state(&self, writer: &mut StateWriter) {
+        save_pixels(&self.bg, writer);
+        save_pixels(&self.obj, writer);
+        writer.u8(self.fetch_step);
+        writer.u8(self.fetch_x);
+        writer.u8(self.tile_attributes);
+        writer.u16(self.tile_address);
+        writer.u8(self.data_low);
+        writer.u8(self.data_high);
+        writer.bool(self.window);
+        writer.u8(self.discard);
+        writer.u8(self.lx);
+        writer.u32(self.dots);
+        writer.u32(self.delay);
+        writer.u8(self.sprites.len() as u8);
+        for &sprite in self.sprites.iter() {
+            writer.u8(sprite as u8)
+        }
+        writer.u32(self.sprite_dots)
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        load_pixels(&mut self.bg, reader)?;
+        load_pixels(&mut self.obj, reader)?;
+        self.fetch_step = reader.u8()?;
+        self.fetch_x = reader.u8()?;
+        self.tile_attributes = reader.u8()?;
+        self.tile_address = reader.u16()?;
+        self.data_low = reader.u8()?;
+        self.data_high = reader.u8()?;
+        self.window = reader.bool()?;
+        self.discard = reader.u8()?;
+        self.lx = reader.u8()?;
+        if self.lx as usize > DISPLAY_WIDTH {
+            return Err(StateError::Invalid("pixel FIFO position"));
+        }
+        self.dots = reader.u32()?;
+        self.delay = reader.u32()?;
+        self.sprites.clear();
+        for _ in 0..reader.u8()? {
+            let sprite = sprite_index(reader)?;
+            self.sprites.push_back(sprite)
+        }
+        self.sprite_dots = reader.u32()?;
+        Ok(())
+    }
+}
+
+pub fn start_line(ppu: &mut Ppu) {
+    let sprites = line_sprites(ppu);
+    let window_start = ppu.window_visible() && ppu.window_x < 7;
+
+    let fifo = &mut ppu.fifo;
+    fifo.bg.clear();
+    fifo.obj.clear();
+    fifo.fetch_step = 0;
+    fifo.fetch_x = 0;
+    fifo.window = false;
+    // SCX fine scroll is applied by dropping pixels at the start of the line.
+    // A window starting left of the screen edge drops its first pixels instead.
+    if window_start {
+        fifo.window = true;
+        fifo.discard = 7 - ppu.window_x
+    } else {
+        fifo.discard = ppu.scx & 0x07
+    }
+    fifo.lx = 0;
+    fifo.dots = 0;
+    fifo.delay = FIRST_FETCH_DOTS;
+    fifo.sprites = sprites;
+    fifo.sprite_dots = 0;
+}
+
+pub fn dot(ppu: &mut Ppu) {
+    ppu.fifo.dots += 1;
+
+    if ppu.fifo.delay > 0 {
+        ppu.fifo.delay -= 1;
+        return;
+    }
+
+    if !ppu.fifo.window && window_reached(ppu) {
+        // The fetcher restarts on the window, discarding queued pixels
+        ppu.fifo.window = true;
+        ppu.fifo.bg.clear();
+        ppu.fifo.fetch_step = 0;
+        ppu.fifo.fetch_x = 0;
+    }
+
+    if fetch_sprite(ppu) {
+        return;
+    }
+
+    fetcher_step(ppu);
+    output_pixel(ppu)
+}
+
+// Sprites selected by the OAM scan are fetched from left to right
+fn line_sprites(ppu: &Ppu) -> VecDeque<usize> {
+    let mut sprites = ppu.line_sprites.clone();
+    sprites.sort_by_key(|&sprite| ppu.oam[sprite * 4 + 1]);
+    sprites.into_iter().collect()
+}
+
+fn window_reached(ppu: &Ppu) -> bool {
+    ppu.window_visible() && ppu.fifo.discard == 0 && ppu.fifo.lx as u16 + 7 >= ppu.window_x as u16
+}
+
+// Returns true while the pixel output is stalled by a sprite fetch
+fn fetch_sprite(ppu: &mut Ppu) -> bool {
+    if !ppu.lcdc.obj_display_enable || ppu.fifo.discard > 0 {
+        return false;
+    }
+
+    let sprite = match ppu.fifo.sprites.front() {
+        Some(&sprite) => sprite,
+        None => return false,
+    };
+    let x = ppu.oam[sprite * 4 + 1];
+    if x as u16 > ppu.fifo.lx as u16 + 8 {
+        return false;
+    }
+
+    // A background fetch in progress is completed first
+    let fetching = ppu.fifo.fetch_step > 0 && ppu.fifo.fetch_step < PUSH_STEP;
+    if ppu.fifo.bg.is_empty() || fetching {
+        fetcher_step(ppu);
+        return true;
+    }
+
+    ppu.fifo.sprite_dots += 1;
+    if ppu.fifo.sprite_dots < SPRITE_FETCH_DOTS {
+        return true;
+    }
+
+    ppu.fifo.sprite_dots = 0;
+    ppu.fifo.sprites.pop_front();
+    load_sprite(ppu, sprite);
+    true
+}
+
+fn load_sprite(ppu: &mut Ppu, sprite: usize) {
+    let index = sprite * 4;
+    let y = ppu.oam[index] as u16;
+    let x = ppu.oam[index + 1];
+    let tile_location = ppu.sprite_tile(ppu.oam[index + 2]);
+    let attributes = ppu.oam[index + 3];
+
+    let height = if ppu.lcdc.obj_size { 16 } else { 8 };
+    let line = ppu.ly as u16 + 16 - y;
+    let line = if (attributes & 0x40) != 0 {
+        height - 1 - line
+    } else {
+        line
+    };
+
+    let tile_bank = if ppu.cgb_mode() && (attributes & 0x08) != 0 {
+        1
+    } else {
+        0
+    };
+    let data_address = 0x8000 + tile_location * 16 + line * 2;
+    let data1 = ppu.read_vram(tile_bank, data_address);
+    let data2 = ppu.read_vram(tile_bank, data_address + 1);
+
+    let by_x = ppu.sprite_priority_by_x();
+
+    while ppu.fifo.obj.len() < 8 {
+        ppu.fifo.obj.push_back(TRANSPARENT)
+    }
+
+    // Sprites partially off the left edge lose their first columns
+    let skip = 8u8.saturating_sub(x) as usize;
+
+    for column in skip..8 {
+        let color_bit = if (attributes & 0x20) != 0 {
+            column
+        } else {
+            7 - column
+        };
+        let color = (((data2 >> color_bit) & 0b1) << 1) | ((data1 >> color_bit) & 0b1);
+
+        if color == 0 {
+            continue;
+        }
+
+        // Sprites are fetched in X order, so on DMG the pixel already in the
+        // FIFO wins. Otherwise the lowest OAM index does.
+        let slot = &mut ppu.fifo.obj[column - skip];
+        if slot.color == 0 || (!by_x && sprite < slot.sprite) {
+            *slot = Pixel {
+                color: color,
+                attributes: attributes,
+                sprite: sprite,
+            }
+        }
+    }
+}
+
+fn fetcher_step(ppu: &mut Ppu) {
+    match ppu.fifo.fetch_step {
+        1 => fetch_tile(ppu),
+        3 => {
+            let bank = tile_bank(ppu.fifo.tile_attributes);
+            ppu.fifo.data_low = ppu.read_vram(bank, ppu.fifo.tile_address)
+        }
+        5 => {
+            let bank = tile_bank(ppu.fifo.tile_attributes);
+            ppu.fifo.data_high = ppu.read_vram(bank, ppu.fifo.tile_address + 1)
+        }
+        PUSH_STEP => {
+            if ppu.fifo.bg.is_empty() {
+                push_tile(ppu);
+                ppu.fifo.fetch_x = ppu.fifo.fetch_x.wrapping_add(1);
+                ppu.fifo.fetch_step = 0
+            }
+            return;
+        }
+        _ => {}
+    }
+    ppu.fifo.fetch_step += 1
+}
+
+fn fetch_tile(ppu: &mut Ppu) {
+    let (map_select, x, y) = if ppu.fifo.window {
+        (ppu.lcdc.window_tile_map_display_select, ppu.fifo.fetch_x, ppu.window_line)
+    } else {
+        // SCX is read on every fetch, so mid-line writes move the next tile
+        (ppu.lcdc.bg_tile_map_display_select,
+         (ppu.scx / 8).wrapping_add(ppu.fifo.fetch_x),
+         ppu.ly.wrapping_add(ppu.scy))
+    };
+
+    let map = if map_select { 0x9c00 } else { 0x9800 };
+    let map_address = map + (y as u16 / 8) * 32 + (x as u16 & 0x1f);
+
+    // In CGB mode, bank 1 of the tile map holds the attributes of each tile
+    let attributes = if ppu.cgb_mode() {
+        ppu.read_vram(1, map_address)
+    } else {
+        0
+    };
+    let tile_num = ppu.read_vram(0, map_address);
+
+    let tile_location = if ppu.lcdc.bg_window_tile_data_select {
+        0x8000 + tile_num as u16 * 16
+    } else {
+        (0x9000 + tile_num as i8 as i32 * 16) as u16
+    };
+
+    let line = y as u16 % 8;
+    let line = if (attributes & 0x40) != 0 { 7 - line } else { line };
+
+    ppu.fifo.tile_attributes = attributes;
+    ppu.fifo.tile_address = tile_location + line * 2
+}
+
+fn push_tile(ppu: &mut Ppu) {
+    let attributes = ppu.fifo.tile_attributes;
+    for column in 0..8 {
+        let color_bit = if (attributes & 0x20) != 0 {
+            column
+        } else {
+            7 - column
+        };
+        let color = (((ppu.fifo.data_high >> color_bit) & 0b1) << 1) |
+                    ((ppu.fifo.data_low >> color_bit) & 0b1);
+        ppu.fifo.bg.push_back(Pixel {
+            color: color,
+            attributes: attributes,
+            sprite: 0,
+        })
+    }
+}
+
+fn output_pixel(ppu: &mut Ppu) {
+    let bg = match ppu.fifo.bg.pop_front() {
+        Some(pixel) => pixel,
+        None => return,
+    };
+
+    if ppu.fifo.discard > 0 {
+        ppu.fifo.discard -= 1;
+        return;
+    }
+
+    let obj = ppu.fifo.obj.pop_front().unwrap_or(TRANSPARENT);
+
+    // Palettes and LCDC are read as each pixel is shifted out
+    let cgb_mode = ppu.cgb_mode();
+    let bg_enabled = ppu.lcdc.bg_display || cgb_mode;
+    let bg_color_num = if bg_enabled { bg.color } else { 0 };
+
+    let obj_visible = obj.color != 0 && ppu.lcdc.obj_display_enable &&
+                      ((obj.attributes & 0x80) == 0 || bg_color_num == 0 ||
+                       (cgb_mode && !ppu.lcdc.bg_display));
+
+    let color = if obj_visible {
+        ppu.obj_color(obj.color, obj.attributes)
+    } else if bg_enabled {
+        ppu.bg_color(bg.color, bg.attributes & 0x07)
+    } else {
+        ppu.blank_color()
+    };
+
+    let x = ppu.fifo.lx as u32;
+    let y = ppu.ly as u32;
+    ppu.set_pixel(x, y, color);
+    ppu.fifo.lx += 1
+}
+
+fn tile_bank(attributes: u8) -> u16 {
+    if (attributes & 0x08) != 0 { 1 } else { 0 }
+}
@@ -0,0 +1,1206 @@
+mod fifo;
+#[cfg(test)]
+mod tests;
+
+use super::Interrupt;
+use super::GameboyType;
+use super::palette::{DmgPalette, Shades};
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
+
+use self::fifo::PixelFifo;
+
+use std::cmp;
+use std::mem;
+use std::u32;
+
+#[derive(Debug,Clone)]
+struct Color {
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+}
+
+impl Color {
+    fn from_rgb(rgb: u32) -> Color {
+        Color {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+            a: 255,
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+struct LCDCtrl {
+    lcd_display_enable: bool,
+    window_tile_map_display_select: bool,
+    window_display_enable: bool,
+    bg_window_tile_data_select: bool,
+    bg_tile_map_display_select: bool,
+    obj_size: bool,
+    obj_display_enable: bool,
+    bg_display: bool,
+}
+
+impl LCDCtrl {
+    fn new() -> LCDCtrl {
+        let mut lcdc = LCDCtrl {
+            lcd_display_enable: false,
+            window_tile_map_display_select: false,
+            window_display_enable: false,
+            bg_window_tile_data_select: false,
+            bg_tile_map_display_select: false,
+            obj_size: false,
+            obj_display_enable: false,
+            bg_display: false,
+        };
+        lcdc.set_flags(0x91);
+        lcdc
+    }
+
+    fn get_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.lcd_display_enable {
+            flags |= 0b1000_0000
+        }
+        if self.window_tile_map_display_select {
+            flags |= 0b0100_0000
+        }
+        if self.window_display_enable {
+            flags |= 0b0010_0000
+        }
+        if self.bg_window_tile_data_select {
+            flags |= 0b0001_0000
+        }
+        if self.bg_tile_map_display_select {
+            flags |= 0b0000_1000
+        }
+        if self.obj_size {
+            flags |= 0b0000_0100
+        }
+        if self.obj_display_enable {
+            flags |= 0b0000_0010
+        }
+        if self.bg_display {
+            flags |= 0b0000_0001
+        }
+        flags
+    }
+
+    fn set_flags(&mut self, flags: u8) {
+        self.lcd_display_enable = (flags & 0b1000_0000) != 0;
+        self.window_tile_map_display_select = (flags & 0b0100_0000) != 0;
+        self.window_display_enable = (flags & 0b0010_0000) != 0;
+        self.bg_window_tile_data_select = (flags & 0b0001_0000) != 0;
+        self.bg_tile_map_display_select = (flags & 0b0000_1000) != 0;
+        self.obj_size = (flags & 0b0000_0100) != 0;
+        self.obj_display_enable = (flags & 0b0000_0010) != 0;
+        self.bg_display = (flags & 0b0000_0001) != 0;
+    }
+}
+
+#[derive(Clone)]
+struct LCDStat {
+    lyc_ly_interrupt: bool,
+    oam_interrupt: bool,
+    vblank_interrupt: bool,
+    hblank_interrupt: bool,
+    coincidence_flag: bool,
+    mode: Mode,
+}
+
+impl LCDStat {
+    fn new() -> LCDStat {
+        LCDStat {
+            lyc_ly_interrupt: false,
+            oam_interrupt: false,
+            vblank_interrupt: false,
+            hblank_interrupt: false,
+            coincidence_flag: false,
+            mode: Mode::VBlank,
+        }
+    }
+
+    fn get_flags(&self) -> u8 {
+        let mut flags: u8 = 0;
+        if self.lyc_ly_interrupt {
+            flags |= 0b0100_0000
+        }
+        if self.oam_interrupt {
+            flags |= 0b0010_0000
+        }
+        if self.vblank_interrupt {
+            flags |= 0b0001_0000
+        }
+        if self.hblank_interrupt {
+            flags |= 0b0000_1000
+        }
+        if self.coincidence_flag {
+            flags |= 0b0000_0100
+        }
+        flags |= self.mode.get_flag();
+        flags
+    }
+
+    fn set_flags(&mut self, flags: u8) {
+        self.lyc_ly_interrupt = (flags & 0b0100_0000) != 0;
+        self.oam_interrupt = (flags & 0b0010_0000) != 0;
+        self.vblank_interrupt = (flags & 0b0001_0000) != 0;
+        self.hblank_interrupt = (flags & 0b0000_1000) != 0;
+        // These are readonly
+        // self.coincidence_flag = (flags & 0b0000_0100) != 0;
+        // self.mode = Mode::from_flags(flags)
+        //
+    }
+}
+
+#[derive(Debug,Clone,Copy)]
+enum Mode {
+    HBlank,
+    VBlank,
+    Oam,
+    VRam,
+}
+
+impl Mode {
+    fn get_flag(self) -> u8 {
+        let f = match self {
+            Mode::HBlank => MODE_HBLANK,
+            Mode::VBlank => MODE_VBLANK,
+            Mode::Oam => MODE_OAM,
+            Mode::VRam => MODE_VRAM,
+        };
+        f as u8
+    }
+}
+
+/// How scanlines are drawn
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Renderer {
+    /// Draws each line at once when mode 3 ends, with a fixed mode 3 length
+    Scanline,
+    /// Draws dot by dot, so mode 3 length and mid-line register writes are
+    /// emulated. Slower.
+    PixelFifo,
+}
+
+pub const OAM_SIZE: usize = 0xa0; // 40 OBJs - 32 bits
+const SPRITE_COUNT: usize = OAM_SIZE / 4;
+
+const FRAMEBUFFER_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+const CLKS_SCREEN_REFRESH: u32 = 70224;
+const DISPLAY_WIDTH: usize = 160;
+const DISPLAY_HEIGHT: usize = 144;
+
+const SPRITES_PER_LINE: usize = 10;
+
+const VRAM_SIZE: usize = 1024 * 16;
+const PALETTE_RAM_SIZE: usize = 64; // 8 palettes - 4 colors - 2 bytes
+
+const MODE_HBLANK: u32 = 0;
+const MODE_VBLANK: u32 = 1;
+const MODE_OAM: u32 = 2;
+const MODE_VRAM: u32 = 3;
+
+const LINE_CYCLES: u32 = 456;
+const HBLANK_CYCLES: u32 = 204;
+const VBLANK_CYCLES: u32 = LINE_CYCLES;
+const OAM_CYCLES: u32 = 80;
+const VRAM_CYCLES: u32 = 172;
+const LINE_153_CYCLES: u32 = 4;
+
+#[derive(Clone)]
+pub struct Ppu {
+    gameboy_type: GameboyType,
+    compat_mode: bool,
+    lcdc: LCDCtrl,
+    lcdstat: LCDStat,
+    scx: u8,
+    scy: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8, // Background palette data
+    obp_0: u8, // Object palette 0 data
+    obp_1: u8, // Object palette 1 data
+    window_y: u8,
+    window_x: u8,
+    window_triggered: bool,
+    window_line: u8, // Internal line counter of the window
+    bcps: u8, // Background palette specification
+    ocps: u8, // Object palette specification
+    opri: u8, // Object priority mode
+    vbk: u8,
+    bg_palette: Box<[u8]>,
+    obj_palette: Box<[u8]>,
+    dmg_palette: DmgPalette,
+    vram: Box<[u8]>,
+    oam: Box<[u8]>,
+    oam_dma_active: bool,
+    line_sprites: Vec<usize>,
+    bg_line: Box<[u8]>,
+    access_locking: bool,
+    framebuffer: Box<[u32]>,
+    front_buffer: Box<[u32]>, // Last complete frame
+    frame_ready: bool,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    mode_cycles: u32,
+    cycles: u32,
+    stat_line: bool,
+    pending_interrupts: u8,
+    skip_frame: bool,
+}
+
+impl Ppu {
+    pub fn new(gameboy_type: GameboyType) -> Ppu {
+        Ppu {
+            gameboy_type: gameboy_type,
+            compat_mode: false,
+            lcdc: LCDCtrl::new(),
+            lcdstat: LCDStat::new(),
+            scx: 0,
+            scy: 0,
+            ly: 144,
+            lyc: 0xff,
+            window_y: 0,
+            window_x: 0,
+            window_triggered: false,
+            window_line: 0,
+            bgp: 0xfc,
+            obp_0: 0xff,
+            obp_1: 0xff,
+            bcps: 0x00,
+            ocps: 0x00,
+            opri: 0x00,
+            vbk: 0,
+            bg_palette: vec![0; PALETTE_RAM_SIZE].into_boxed_slice(),
+            obj_palette: vec![0; PALETTE_RAM_SIZE].into_boxed_slice(),
+            dmg_palette: DmgPalette::default(),
+            vram: vec![0; VRAM_SIZE].into_boxed_slice(),
+            oam: vec![0; OAM_SIZE].into_boxed_slice(),
+            oam_dma_active: false,
+            line_sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            bg_line: vec![0; DISPLAY_WIDTH].into_boxed_slice(),
+            access_locking: true,
+            framebuffer: vec![0; FRAMEBUFFER_SIZE].into_boxed_slice(),
+            front_buffer: vec![0; FRAMEBUFFER_SIZE].into_boxed_slice(),
+            frame_ready: false,
+            renderer: Renderer::Scanline,
+            fifo: PixelFifo::new(),
+            mode_cycles: 0,
+            cycles: 0,
+            stat_line: false,
+            pending_interrupts: 0,
+            skip_frame: false,
+        }
+    }
+
+    /// Puts the PPU in its power-on state, for running a boot ROM
+    pub fn power_on(&mut self) {
+        self.lcdc.set_flags(0x00);
+        self.lcdstat = LCDStat::new();
+        self.lcdstat.mode = Mode::HBlank;
+        self.ly = 0;
+        self.lyc = 0;
+        self.bgp = 0;
+        self.obp_0 = 0;
+        self.obp_1 = 0;
+        self.mode_cycles = 0
+    }
+
+    /// Enables DMG compatibility mode on CGB, where DMG palettes are mapped
+    /// through the first CGB palettes.
+    pub fn set_compatibility_mode(&mut self, enabled: bool) {
+        self.compat_mode = enabled
+    }
+
+    /// Set when a frame has been completed, until `clear_frame_ready`
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    pub fn clear_frame_ready(&mut self) {
+        self.frame_ready = false
+    }
+
+    /// Last complete frame, 160x144 pixels as 0xAARRGGBB
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.front_buffer
+    }
+
+    /// Colours used for the four shades of DMG games
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer
+    }
+
+    /// Blocks CPU access to VRAM and OAM while the PPU is using them.
+    /// Can be disabled for debugging.
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.access_locking = enabled
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x8000...0x9fff if self.vram_locked() => {}
+            0xfe00...0xfe9f if self.oam_locked() => {}
+            0x8000...0x9fff => {
+                let addr = addr - 0x8000;
+                let offset = self.vbk_offset();
+                self.vram[(addr + offset) as usize] = val
+            }
+            0xfe00...0xfe9f => self.oam[(addr - 0xfe00) as usize] = val,
+            0xff40 => {
+                let was_enabled = self.lcdc.lcd_display_enable;
+                self.lcdc.set_flags(val);
+                match (was_enabled, self.lcdc.lcd_display_enable) {
+                    (true, false) => self.lcd_off(),
+                    (false, true) => self.lcd_on(),
+                    _ => {}
+                }
+                self.update_stat_line()
+            }
+            0xff41 => self.write_stat(val),
+            0xff42 => self.scy = val,
+            0xff43 => self.scx = val,
+            0xff44 => {} // 0xff44 => self.ly = val, READONLY
+            0xff45 => {
+                self.lyc = val;
+                self.update_stat_line()
+            }
+            0xff47 => self.bgp = val,
+            0xff48 => self.obp_0 = val,
+            0xff49 => self.obp_1 = val,
+            0xff4a => self.window_y = val,
+            0xff4b => self.window_x = val,
+            0xff4f => self.vbk = val & 0x01,
+            0xff68 => self.bcps = val & 0xbf,
+            0xff69 => write_palette_data(&mut self.bg_palette, &mut self.bcps, val),
+            0xff6a => self.ocps = val & 0xbf,
+            0xff6b => write_palette_data(&mut self.obj_palette, &mut self.ocps, val),
+            0xff6c => self.opri = val & 0x01,
+            _ => panic!("Write not implmented for 0x{:x}", addr),
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000...0x9fff if self.vram_locked() => 0xff,
+            0xfe00...0xfe9f if self.oam_locked() => 0xff,
+            _ => self.peek(addr),
+        }
+    }
+
+    /// Reads like `read`, but VRAM and OAM are never locked
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000...0x9fff => {
+                let addr = addr - 0x8000;
+                let offset = self.vbk_offset();
+                self.vram[(addr + offset) as usize]
+            }
+            0xfe00...0xfe9f => self.oam[(addr - 0xfe00) as usize],
+            0xff40 => self.lcdc.get_flags(),
+            0xff41 => 0x80 | self.lcdstat.get_flags(),
+            0xff42 => self.scy,
+            0xff43 => self.scx,
+            0xff44 => self.ly,
+            0xff45 => self.lyc,
+            0xff47 => self.bgp,
+            0xff48 => self.obp_0,
+            0xff49 => self.obp_1,
+            0xff4a => self.window_y,
+            0xff4b => self.window_x,
+            0xff4f => 0xfe | self.vbk,
+            0xff68 => 0x40 | self.bcps,
+            0xff69 => self.bg_palette[(self.bcps & 0x3f) as usize],
+            0xff6a => 0x40 | self.ocps,
+            0xff6b => self.obj_palette[(self.ocps & 0x3f) as usize],
+            0xff6c => 0xfe | self.opri,
+            _ => panic!("Read not implmented for 0x{:x}", addr),
+        }
+    }
+
+    pub fn cycle_flush(&mut self, cycle_count: u32) -> u8 {
+        self.mode_cycles += cycle_count;
+
+        if self.lcdc.lcd_display_enable {
+
+            self.cycles += cycle_count;
+
+            loop {
+                if let (Mode::VRam, Renderer::PixelFifo) = (self.lcdstat.mode, self.renderer) {
+                    while !self.fifo.done() && self.fifo.dots() < self.mode_cycles {
+                        fifo::dot(self)
+                    }
+                }
+                if self.mode_cycles < self.mode_length() {
+                    break;
+                }
+
+                self.mode_cycles -= self.mode_length();
+                self.next_mode();
+                self.update_stat_line()
+            }
+
+            // LY already reads 0 a few cycles into line 153
+            if let Mode::VBlank = self.lcdstat.mode {
+                if self.ly == 153 && self.mode_cycles >= LINE_153_CYCLES {
+                    self.ly = 0;
+                    self.update_stat_line()
+                }
+            }
+        } else {
+            // Keep delivering frames so the frontend isn't stuck on the last image
+            if self.mode_cycles >= CLKS_SCREEN_REFRESH {
+                self.mode_cycles -= CLKS_SCREEN_REFRESH;
+                self.send_blank_frame()
+            }
+        }
+
+        let interrupt = self.pending_interrupts;
+        self.pending_interrupts = 0;
+        interrupt
+    }
+
+    pub fn cycles_until_event(&self) -> u32 {
+        if !self.lcdc.lcd_display_enable {
+            return CLKS_SCREEN_REFRESH.saturating_sub(self.mode_cycles);
+        }
+        match (self.lcdstat.mode, self.renderer) {
+            (Mode::VBlank, _) if self.ly == 153 && self.mode_cycles < LINE_153_CYCLES => {
+                LINE_153_CYCLES - self.mode_cycles
+            }
+            // Mode 3 is at least this long
+            (Mode::VRam, Renderer::PixelFifo) => VRAM_CYCLES.saturating_sub(self.mode_cycles),
+            _ => self.mode_length().saturating_sub(self.mode_cycles),
+        }
+    }
+
+    // The screen goes blank, LY is held at 0 and STAT reports mode 0
+    fn lcd_off(&mut self) {
+        self.ly = 0;
+        self.lcdstat.mode = Mode::HBlank;
+        self.mode_cycles = 0;
+        self.send_blank_frame()
+    }
+
+    // Rendering restarts at line 0, but the first frame isn't displayed
+    fn lcd_on(&mut self) {
+        self.ly = 0;
+        self.lcdstat.mode = Mode::Oam;
+        self.mode_cycles = 0;
+        self.skip_frame = true;
+        self.latch_window_y();
+        self.oam_scan()
+    }
+
+    fn send_blank_frame(&mut self) {
+        let blank = argb(&self.blank_color());
+        for pixel in self.front_buffer.iter_mut() {
+            *pixel = blank
+        }
+        self.frame_ready = true
+    }
+
+    fn send_frame(&mut self) {
+        mem::swap(&mut self.framebuffer, &mut self.front_buffer);
+        self.frame_ready = true
+    }
+
+    fn mode_length(&self) -> u32 {
+        match (self.lcdstat.mode, self.renderer) {
+            (Mode::HBlank, Renderer::Scanline) => HBLANK_CYCLES,
+            (Mode::HBlank, Renderer::PixelFifo) => LINE_CYCLES - OAM_CYCLES - self.fifo.dots(),
+            (Mode::VBlank, _) => VBLANK_CYCLES,
+            (Mode::Oam, _) => OAM_CYCLES,
+            (Mode::VRam, Renderer::Scanline) => VRAM_CYCLES,
+            (Mode::VRam, Renderer::PixelFifo) => {
+                if self.fifo.done() {
+                    self.fifo.dots()
+                } else {
+                    u32::MAX
+                }
+            }
+        }
+    }
+
+    fn next_mode(&mut self) {
+        self.lcdstat.mode = match self.lcdstat.mode {
+            Mode::Oam => {
+                if let Renderer::PixelFifo = self.renderer {
+                    fifo::start_line(self)
+                }
+                Mode::VRam
+            }
+            Mode::VRam => {
+                let window_drawn = match self.renderer {
+                    Renderer::Scanline => {
+                        self.draw_scanline();
+                        self.window_visible()
+                    }
+                    Renderer::PixelFifo => self.fifo.window_drawn(),
+                };
+                // The window only advances a line when it was actually drawn
+                if window_drawn {
+                    self.window_line = self.window_line.wrapping_add(1)
+                }
+                Mode::HBlank
+            }
+            Mode::HBlank => {
+                self.ly += 1;
+                if self.ly == 144 {
+                    if self.skip_frame {
+                        self.skip_frame = false;
+                        self.send_blank_frame()
+                    } else {
+                        self.send_frame()
+                    }
+                    self.pending_interrupts |= Interrupt::VBlank.flag();
+                    self.cycles = 0;
+                    Mode::VBlank
+                } else {
+                    self.latch_window_y();
+                    self.oam_scan();
+                    Mode::Oam
+                }
+            }
+            Mode::VBlank => {
+                // LY has normally wrapped to 0 early in line 153
+                if self.ly == 153 || self.ly == 0 {
+                    self.ly = 0;
+                    self.latch_window_y();
+                    self.oam_scan();
+                    Mode::Oam
+                } else {
+                    self.ly += 1;
+                    Mode::VBlank
+                }
+            }
+        }
+    }
+
+    // The STAT interrupt is requested on the rising edge of the OR of all
+    // enabled sources, so a source can't trigger while another holds it high.
+    fn update_stat_line(&mut self) {
+        self.lcdstat.coincidence_flag = self.ly == self.lyc;
+
+        let stat = &self.lcdstat;
+        let mode_source = match stat.mode {
+            Mode::HBlank => stat.hblank_interrupt,
+            Mode::VBlank => stat.vblank_interrupt,
+            Mode::Oam => stat.oam_interrupt,
+            Mode::VRam => false,
+        };
+        let line = self.lcdc.lcd_display_enable &&
+                   ((stat.lyc_ly_interrupt && stat.coincidence_flag) || mode_source);
+
+        if line && !self.stat_line {
+            self.pending_interrupts |= Interrupt::LCDStat.flag()
+        }
+        self.stat_line = line
+    }
+
+    fn write_stat(&mut self, val: u8) {
+        // On DMG, writing STAT enables every source for a cycle, which can
+        // trigger a spurious interrupt
+        if let GameboyType::Dmg = self.gameboy_type {
+            self.lcdstat.set_flags(0xff);
+            self.update_stat_line()
+        }
+        self.lcdstat.set_flags(val);
+        self.update_stat_line()
+    }
+
+    pub fn oam_dma_write(&mut self, index: usize, val: u8) {
+        self.oam[index] = val
+    }
+
+    /// OAM is inaccessible to the PPU while a DMA transfer is running
+    pub fn set_oam_dma_active(&mut self, active: bool) {
+        self.oam_dma_active = active
+    }
+
+    // VRAM is read by the PPU during pixel transfer
+    fn vram_locked(&self) -> bool {
+        if !self.access_locking || !self.lcdc.lcd_display_enable {
+            return false;
+        }
+        match self.lcdstat.mode {
+            Mode::VRam => true,
+            _ => false,
+        }
+    }
+
+    // OAM is read by the PPU during OAM search and pixel transfer
+    fn oam_locked(&self) -> bool {
+        if !self.access_locking || !self.lcdc.lcd_display_enable {
+            return false;
+        }
+        match self.lcdstat.mode {
+            Mode::Oam | Mode::VRam => true,
+            _ => false,
+        }
+    }
+
+    fn vbk_offset(&self) -> u16 {
+        match self.gameboy_type {
+            GameboyType::Cgb => self.vbk as u16 * 0x2000,
+            GameboyType::Dmg => 0,
+        }
+    }
+
+    fn read_vram(&self, bank: u16, addr: u16) -> u8 {
+        self.vram[((addr - 0x8000) + bank * 0x2000) as usize]
+    }
+
+    fn cgb_mode(&self) -> bool {
+        match self.gameboy_type {
+            GameboyType::Cgb => !self.compat_mode,
+            GameboyType::Dmg => false,
+        }
+    }
+
+    // WY is compared with LY at the start of each line, once they have
+    // matched the window can be shown for the rest of the frame
+    fn latch_window_y(&mut self) {
+        if self.ly == 0 {
+            self.window_triggered = false;
+            self.window_line = 0
+        }
+        if self.ly == self.window_y {
+            self.window_triggered = true
+        }
+    }
+
+    fn window_visible(&self) -> bool {
+        self.lcdc.window_display_enable && self.window_triggered && self.window_x <= 166
+    }
+
+    fn draw_scanline(&mut self) {
+        // In CGB mode, LCDC bit 0 only takes priority away from the background
+        if self.lcdc.bg_display || self.cgb_mode() {
+            self.render_tiles()
+        } else {
+            for pixel in 0..DISPLAY_WIDTH {
+                self.bg_line[pixel] = 0;
+                let color = self.blank_color();
+                self.set_pixel(pixel as u32, self.ly as u32, color)
+            }
+        }
+
+        if self.lcdc.obj_display_enable {
+            self.render_sprites()
+        }
+    }
+
+    // Selects the first 10 sprites in OAM that overlap the line
+    fn oam_scan(&mut self) {
+        self.line_sprites.clear();
+        if self.oam_dma_active {
+            return;
+        }
+
+        let height = if self.lcdc.obj_size { 16 } else { 8 };
+        let line = self.ly as u16 + 16;
+
+        for sprite in 0..SPRITE_COUNT {
+            let y = self.oam[sprite * 4] as u16;
+            if line >= y && line < y + height {
+                self.line_sprites.push(sprite);
+                if self.line_sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+    }
+
+    // On DMG, and when selected by OPRI on CGB, the sprite with the lowest X
+    // coordinate has priority. Otherwise only the OAM index decides.
+    fn sprite_priority_by_x(&self) -> bool {
+        !self.cgb_mode() || (self.opri & 0x01) != 0
+    }
+
+    fn render_tiles(&mut self) {
+
+        let scanline = self.ly;
+
+        let scroll_y = self.scy;
+        let scroll_x = self.scx;
+        let window_x = self.window_x as u16;
+        let window_visible = self.window_visible();
+
+        let (tile_data, unsigned): (u16, bool) = if self.lcdc.bg_window_tile_data_select {
+            (0x8000, true)
+        } else {
+            (0x8800, false)
+        };
+
+        let window_mem = if self.lcdc.window_tile_map_display_select {
+            0x9c00
+        } else {
+            0x9800
+        };
+        let background_mem = if self.lcdc.bg_tile_map_display_select {
+            0x9c00
+        } else {
+            0x9800
+        };
+
+        for pixel in 0..160 {
+            // The window starts at screen X WX-7, WX below 7 cuts off its left side
+            let (map, x_pos, y_pos) = if window_visible && pixel + 7 >= window_x {
+                (window_mem, (pixel + 7 - window_x) as u8, self.window_line)
+            } else {
+                (background_mem, (pixel as u8).wrapping_add(scroll_x), scroll_y.wrapping_add(scanline))
+            };
+            let pixel = pixel as u8;
+
+            let tile_row: u16 = (y_pos / 8) as u16 * 32;
+            let tile_address = map + tile_row + (x_pos / 8) as u16;
+
+            // In CGB mode, bank 1 of the tile map holds the attributes of each tile
+            let attributes = if self.cgb_mode() {
+                self.read_vram(1, tile_address)
+            } else {
+                0
+            };
+            let tile_bank = if (attributes & 0x08) != 0 { 1 } else { 0 };
+
+            let tile_num: i16 = if unsigned {
+                self.read_vram(0, tile_address) as u16 as i16
+            } else {
+                self.read_vram(0, tile_address) as i8 as i16
+            };
+
+            let tile_location: u16 = if unsigned {
+                tile_data + (tile_num as u16 * 16)
+            } else {
+                tile_data + ((tile_num + 128) * 16) as u16
+            };
+
+            let line = y_pos as u16 % 8;
+            let line = if (attributes & 0x40) != 0 { 7 - line } else { line };
+            let line = line * 2;
+            let data1 = self.read_vram(tile_bank, tile_location + line);
+            let data2 = self.read_vram(tile_bank, tile_location + line + 1);
+
+            let color_bit = ((x_pos as i32 % 8) - 7) * -1;
+            let color_bit = if (attributes & 0x20) != 0 {
+                (color_bit - 7) * -1
+            } else {
+                color_bit
+            };
+
+            let color_num = ((data2 >> color_bit) & 0b1) << 1;
+            let color_num = color_num | ((data1 >> color_bit) & 0b1);
+
+            let color = self.bg_color(color_num, attributes & 0x07);
+            self.bg_line[pixel as usize] = color_num;
+            self.set_pixel(pixel as u32, scanline as u32, color)
+
+        }
+    }
+
+    fn render_sprites(&mut self) {
+
+        let use_8x16 = self.lcdc.obj_size;
+        let scanline = self.ly;
+
+        let mut sprites = self.line_sprites.clone();
+        if self.sprite_priority_by_x() {
+            // Stable, so sprites at the same X stay in OAM order
+            sprites.sort_by_key(|&sprite| self.oam[sprite * 4 + 1]);
+        }
+
+        // Colour number and attributes of the highest priority sprite pixel
+        let mut line = [(0u8, 0u8); DISPLAY_WIDTH];
+
+        for sprite in sprites {
+            let index = sprite * 4;
+
+            let y_pos = self.oam[index] as i32 - 16;
+            let x_pos = self.oam[index + 1] as i32 - 8;
+            let tile_location = self.sprite_tile(self.oam[index + 2]);
+            let attributes = self.oam[index + 3];
+            let y_flip = (attributes & 0x40) != 0;
+            let x_flip = (attributes & 0x20) != 0;
+
+            let y_size = if use_8x16 { 16 } else { 8 };
+
+            let line_num = scanline as i32 - y_pos;
+            let line_num = if y_flip {
+                y_size - 1 - line_num
+            } else {
+                line_num
+            };
+
+            let data_address = 0x8000 + (tile_location * 16) + line_num as u16 * 2;
+
+            let tile_bank = if self.cgb_mode() && (attributes & 0x08) != 0 {
+                1
+            } else {
+                0
+            };
+            let data1 = self.read_vram(tile_bank, data_address);
+            let data2 = self.read_vram(tile_bank, data_address + 1);
+
+            for column in 0..8 {
+                let pixel = x_pos + column;
+                if pixel < 0 || pixel >= DISPLAY_WIDTH as i32 || line[pixel as usize].0 != 0 {
+                    continue;
+                }
+
+                let color_bit = if x_flip { column } else { 7 - column };
+
+                let color_num = ((data2 >> color_bit) & 0b1) << 1;
+                let color_num = color_num | ((data1 >> color_bit) & 0b1);
+
+                // Color 0 is transparent for sprites, regardless of palette
+                if color_num != 0 {
+                    line[pixel as usize] = (color_num, attributes)
+                }
+            }
+        }
+
+        // A sprite behind the background still hides lower priority sprites
+        let bg_priority = self.lcdc.bg_display || !self.cgb_mode();
+
+        for pixel in 0..DISPLAY_WIDTH {
+            let (color_num, attributes) = line[pixel];
+            if color_num == 0 {
+                continue;
+            }
+
+            let obj_to_bg_pri = (attributes & 0x80) != 0;
+            if obj_to_bg_pri && bg_priority && self.bg_line[pixel] != 0 {
+                continue;
+            }
+
+            let color = self.obj_color(color_num, attributes);
+            self.set_pixel(pixel as u32, scanline as u32, color)
+        }
+    }
+
+    // 8x16 sprites ignore bit 0 of the tile index, the top half is always the
+    // even tile
+    fn sprite_tile(&self, tile: u8) -> u16 {
+        if self.lcdc.obj_size {
+            (tile & 0xfe) as u16
+        } else {
+            tile as u16
+        }
+    }
+
+    fn bg_color(&self, color_id: u8, cgb_palette: u8) -> Color {
+        if self.cgb_mode() {
+            cgb_color(&self.bg_palette, cgb_palette, color_id)
+        } else if self.compat_mode {
+            cgb_color(&self.bg_palette, 0, get_shade(color_id, self.bgp))
+        } else {
+            get_color(color_id, self.bgp, &self.dmg_palette.bg)
+        }
+    }
+
+    fn obj_color(&self, color_id: u8, attributes: u8) -> Color {
+        let (palette_num, cgb_palette, shades) = if (attributes & 0x10) != 0 {
+            (self.obp_1, 1, &self.dmg_palette.obj1)
+        } else {
+            (self.obp_0, 0, &self.dmg_palette.obj0)
+        };
+
+        if self.cgb_mode() {
+            cgb_color(&self.obj_palette, attributes & 0x07, color_id)
+        } else if self.compat_mode {
+            cgb_color(&self.obj_palette, cgb_palette, get_shade(color_id, palette_num))
+        } else {
+            get_color(color_id, palette_num, shades)
+        }
+    }
+
+    // Shown while the LCD is off, and for the background when it's disabled
+    fn blank_color(&self) -> Color {
+        if self.cgb_mode() || self.compat_mode {
+            Color::from_rgb(0xffffff)
+        } else {
+            Color::from_rgb(self.dmg_palette.bg[0])
+        }
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+
+        let offset = ((y * 160) + x) as usize;
+
+        self.framebuffer[offset] = argb(&color)
+    }
+}
+
+fn argb(color: &Color) -> u32 {
+    ((color.a as u32) << 24) | ((color.r as u32) << 16) | ((color.g as u32) << 8) |
+    (color.b as u32)
+}
+
+fn get_color(color_id: u8, palette_num: u8, shades: &Shades) -> Color {
+    Color::from_rgb(shades[get_shade(color_id, palette_num) as usize])
+}
+
+fn get_shade(color_id: u8, palette_num: u8) -> u8 {
+    (palette_num >> (color_id * 2)) & 0b11
+}
+
+fn cgb_color(palette_ram: &[u8], palette: u8, color_id: u8) -> Color {
+    let offset = (palette as usize * 4 + color_id as usize) * 2;
+    let rgb = (palette_ram[offset] as u16) | ((palette_ram[offset + 1] as u16) << 8);
+    Color {
+        r: expand_5bit(rgb),
+        g: expand_5bit(rgb >> 5),
+        b: expand_5bit(rgb >> 10),
+        a: 255,
+    }
+}
+
+fn expand_5bit(val: u16) -> u8 {
+    let val = (val & 0x1f) as u8;
+    (val << 3) | (val >> 2)
+}
+
+// Frames use few colours, so pixels are stored as indices into a table of
+// them, packed into 1, 2, 4 or 8 bits. More than 256 colours, or no pixels,
+// are stored as they are after an empty table.
+fn save_pixels(w: &mut StateWriter, pixels: &[u32]) {
+    let mut colours = pixels.to_vec();
+    colours.sort();
+    colours.dedup();
+    if colours.is_empty() || colours.len() > 256 {
+        w.u16(0);
+        for &pixel in pixels {
+            w.u32(pixel)
+        }
+        return;
+    }
+
+    w.u16(colours.len() as u16);
+    for &colour in colours.iter() {
+        w.u32(colour)
+    }
+    let bits = index_bits(colours.len());
+    let packed: Vec<u8> = pixels.chunks(8 / bits).map(|chunk| {
+        chunk.iter().enumerate().fold(0, |byte, (i, pixel)| {
+            byte | (colours.binary_search(pixel).unwrap() as u8) << (i * bits)
+        })
+    }).collect();
+    w.bytes(&packed)
+}
+
+fn load_pixels(r: &mut StateReader, pixels: &mut [u32]) -> Result<(), StateError> {
+    let colour_count = r.u16()? as usize;
+    if colour_count == 0 {
+        for pixel in pixels.iter_mut() {
+            *pixel = r.u32()?
+        }
+        return Ok(());
+    }
+    if colour_count > 256 {
+        return Err(StateError::Invalid("colour count"));
+    }
+
+    let mut colours = Vec::with_capacity(colour_count);
+    for _ in 0..colour_count {
+        colours.push(r.u32()?)
+    }
+    let bits = index_bits(colour_count);
+    let packed = r.bytes()?;
+    if packed.len() != pixels.chunks(8 / bits).len() {
+        return Err(StateError::Invalid("pixel count"));
+    }
+    let mask = ((1 << bits) - 1) as u8;
+    for (chunk, &byte) in pixels.chunks_mut(8 / bits).zip(packed) {
+        for (i, pixel) in chunk.iter_mut().enumerate() {
+            let index = (byte >> (i * bits)) & mask;
+            *pixel = *colours.get(index as usize).ok_or(StateError::Invalid("colour index"))?
+        }
+    }
+    Ok(())
+}
+
+fn index_bits(colour_count: usize) -> usize {
+    match colour_count {
+        0...2 => 1,
+        3...4 => 2,
+        5...16 => 4,
+        _ => 8,
+    }
+}
+
+impl SaveState for Ppu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"PPU ", |w| {
+            w.bool(self.compat_mode);
+            w.u8(self.lcdc.get_flags());
+            w.u8(self.lcdstat.get_flags());
+            w.u8(self.scx);
+            w.u8(self.scy);
+            w.u8(self.ly);
+            w.u8(self.lyc);
+            w.u8(self.bgp);
+            w.u8(self.obp_0);
+            w.u8(self.obp_1);
+            w.u8(self.window_y);
+            w.u8(self.window_x);
+            w.bool(self.window_triggered);
+            w.u8(self.window_line);
+            w.u8(self.bcps);
+            w.u8(self.ocps);
+            w.u8(self.opri);
+            w.u8(self.vbk);
+            w.bytes(&self.bg_palette);
+            w.bytes(&self.obj_palette);
+            w.bytes(&self.vram);
+            w.bytes(&self.oam);
+            w.bool(self.oam_dma_active);
+            w.u8(self.line_sprites.len() as u8);
+            for &sprite in self.line_sprites.iter() {
+                w.u8(sprite as u8)
+            }
+            w.bytes(&self.bg_line);
+            // The lines drawn so far of this frame, and the last complete one
+            save_pixels(w, &self.framebuffer[..self.drawn_lines() * DISPLAY_WIDTH]);
+            save_pixels(w, &self.front_buffer);
+            self.fifo.save_state(w);
+            w.u32(self.mode_cycles);
+            w.u32(self.cycles);
+            w.bool(self.stat_line);
+            w.u8(self.pending_interrupts);
+            w.bool(self.skip_frame)
+        })
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"PPU ", |r| {
+            self.compat_mode = r.bool()?;
+            self.lcdc.set_flags(r.u8()?);
+            let stat = r.u8()?;
+            self.lcdstat.set_flags(stat);
+            self.lcdstat.coincidence_flag = (stat & 0b0000_0100) != 0;
+            self.lcdstat.mode = match stat as u32 & 0b11 {
+                MODE_HBLANK => Mode::HBlank,
+                MODE_VBLANK => Mode::VBlank,
+                MODE_OAM => Mode::Oam,
+                _ => Mode::VRam,
+            };
+            self.scx = r.u8()?;
+            self.scy = r.u8()?;
+            self.ly = r.u8()?;
+            self.check_line()?;
+            self.lyc = r.u8()?;
+            self.bgp = r.u8()?;
+            self.obp_0 = r.u8()?;
+            self.obp_1 = r.u8()?;
+            self.window_y = r.u8()?;
+            self.window_x = r.u8()?;
+            self.window_triggered = r.bool()?;
+            self.window_line = r.u8()?;
+            self.bcps = r.u8()? & 0xbf;
+            self.ocps = r.u8()? & 0xbf;
+            self.opri = r.u8()? & 0x01;
+            self.vbk = r.u8()? & 0x01;
+            r.bytes_into(&mut self.bg_palette)?;
+            r.bytes_into(&mut self.obj_palette)?;
+            r.bytes_into(&mut self.vram)?;
+            r.bytes_into(&mut self.oam)?;
+            self.oam_dma_active = r.bool()?;
+            self.line_sprites.clear();
+            let sprite_count = r.u8()? as usize;
+            if sprite_count > SPRITES_PER_LINE {
+                return Err(StateError::Invalid("sprite count"));
+            }
+            for _ in 0..sprite_count {
+                match r.u8()? as usize {
+                    sprite if sprite < SPRITE_COUNT => self.line_sprites.push(sprite),
+                    _ => return Err(StateError::Invalid("sprite index")),
+                }
+            }
+            r.bytes_into(&mut self.bg_line)?;
+            if r.version() < 3 {
+                for pixel in self.framebuffer.iter_mut().chain(self.front_buffer.iter_mut()) {
+                    *pixel = r.u32()?
+                }
+            } else {
+                let drawn = self.drawn_lines() * DISPLAY_WIDTH;
+                load_pixels(r, &mut self.framebuffer[..drawn])?;
+                load_pixels(r, &mut self.front_buffer)?;
+            }
+            self.fifo.load_state(r)?;
+            self.mode_cycles = r.u32()?;
+            self.cycles = r.u32()?;
+            self.stat_line = r.bool()?;
+            self.pending_interrupts = r.u8()?;
+            self.skip_frame = r.bool()?;
+            self.check_state()
+        })
+    }
+}
+
+impl Ppu {
+    // Lines of the frame being drawn that are already in the framebuffer, the
+    // rest is drawn before the frame is shown
+    fn drawn_lines(&self) -> usize {
+        match self.lcdstat.mode {
+            Mode::VBlank => 0,
+            _ if !self.lcdc.lcd_display_enable => 0,
+            _ => cmp::min(self.ly as usize + 1, DISPLAY_HEIGHT),
+        }
+    }
+
+    // Rendering indexes the framebuffer with LY, so a loaded state must be
+    // one the PPU could have reached
+    fn check_line(&self) -> Result<(), StateError> {
+        let visible = match self.lcdstat.mode {
+            Mode::VBlank => false,
+            _ => self.lcdc.lcd_display_enable,
+        };
+        if self.ly > 153 || (visible && self.ly as usize >= DISPLAY_HEIGHT) {
+            return Err(StateError::Invalid("LY"));
+        }
+        Ok(())
+    }
+
+    fn check_state(&self) -> Result<(), StateError> {
+        if self.window_line as usize > DISPLAY_HEIGHT {
+            return Err(StateError::Invalid("window line"));
+        }
+        if !self.lcdc.lcd_display_enable {
+            return if self.mode_cycles < CLKS_SCREEN_REFRESH {
+                Ok(())
+            } else {
+                Err(StateError::Invalid("PPU mode cycles"))
+            };
+        }
+
+        // The longest each mode can last, with either renderer
+        let max_cycles = match self.lcdstat.mode {
+            Mode::HBlank => HBLANK_CYCLES,
+            Mode::VBlank => VBLANK_CYCLES,
+            Mode::Oam => OAM_CYCLES,
+            Mode::VRam => LINE_CYCLES - OAM_CYCLES,
+        };
+        if self.mode_cycles >= max_cycles {
+            return Err(StateError::Invalid("PPU mode cycles"));
+        }
+        Ok(())
+    }
+}
+
+fn write_palette_data(palette_ram: &mut [u8], spec: &mut u8, val: u8) {
+    let index = *spec & 0x3f;
+    palette_ram[index as usize] = val;
+    if (*spec & 0x80) != 0 {
+        *spec = 0x80 | ((index + 1) & 0x3f)
+    }
+}
@@ -0,0 +1,253 @@
+// Renders frames with register and memory writes only, and compares them with
+// reference screenshots in tests/roms/screenshots/scenes. The references are
+// checked against frames built by hand from the rules being tested. Also
+// checks what the CPU sees of the PPU while it runs.
+
+use super::{Ppu, Renderer, DISPLAY_WIDTH, DISPLAY_HEIGHT, CLKS_SCREEN_REFRESH, OAM_CYCLES};
+use super::super::{GameboyType, Interrupt};
+use super::super::palette::DmgPalette;
+use png;
+
+const SPRITES_8X16_FLIP: &'static [u8] =
+    include_bytes!("../../../tests/roms/screenshots/scenes/sprites_8x16_flip.png");
+
+// Colour number of each pixel of the 8x16 test object. Asymmetric both ways,
+// with some transparent pixels.
+fn pattern(row: usize, column: usize) -> u8 {
+    if (row * 3 + column) % 4 == 0 {
+        0
+    } else {
+        1 + ((row + 2 * column) % 3) as u8
+    }
+}
+
+fn render_frame<F: Fn(&mut Ppu)>(renderer: Renderer, setup: F) -> Box<[u32]> {
+    let mut ppu = Ppu::new(GameboyType::Dmg);
+    ppu.set_renderer(renderer);
+
+    ppu.write(0xff40, 0x00);
+    setup(&mut ppu);
+
+    let mut frame = None;
+    for _ in 0..(CLKS_SCREEN_REFRESH * 3 / 4) {
+        ppu.cycle_flush(4);
+        if ppu.frame_ready() {
+            ppu.clear_frame_ready();
+            frame = Some(ppu.framebuffer().to_vec().into_boxed_slice())
+        }
+    }
+    frame.expect("no frame rendered")
+}
+
+fn assert_matches_reference(frame: &[u32], reference: &[u8]) {
+    let reference = png::decode(reference).unwrap();
+    assert_eq!((reference.width, reference.height), (DISPLAY_WIDTH, DISPLAY_HEIGHT));
+
+    let mut mismatches = Vec::new();
+    for y in 0..DISPLAY_HEIGHT {
+        for x in 0..DISPLAY_WIDTH {
+            let offset = y * DISPLAY_WIDTH + x;
+            if frame[offset] & 0xffffff != reference.pixels[offset] {
+                mismatches.push((x, y))
+            }
+        }
+    }
+
+    assert!(mismatches.is_empty(),
+            "{} pixels differ from the reference, first at {:?}",
+            mismatches.len(),
+            mismatches[0]);
+}
+
+fn setup_8x16_sprites(ppu: &mut Ppu) {
+    // Tile 2 and 3 hold the object, tile 4 is garbage that shows up if the
+    // odd tile index is used for the top half
+    for row in 0..16 {
+        let mut low = 0;
+        let mut high = 0;
+        for column in 0..8 {
+            let color = pattern(row, column);
+            low |= (color & 0b01) << (7 - column);
+            high |= ((color & 0b10) >> 1) << (7 - column);
+        }
+        let address = 0x8020 + row as u16 * 2;
+        ppu.write(address, low);
+        ppu.write(address + 1, high);
+    }
+    for address in 0x8040..0x8050 {
+        ppu.write(address, 0xa5)
+    }
+
+    // No flip, X flip, Y flip and both, all using the odd tile index
+    let sprites = [(8, 0x00), (24, 0x20), (40, 0x40), (56, 0x60)];
+    for (i, &(x, flags)) in sprites.iter().enumerate() {
+        let address = 0xfe00 + i as u16 * 4;
+        ppu.write(address, 8 + 16);
+        ppu.write(address + 1, x + 8);
+        ppu.write(address + 2, 3);
+        ppu.write(address + 3, flags);
+    }
+
+    ppu.write(0xff47, 0xe4);
+    ppu.write(0xff48, 0xe4);
+    ppu.write(0xff40, 0x87)
+}
+
+// Each object covers rows 8-23. Tile 2 is its top half and tile 3 the bottom
+// one, Y flip mirrors all 16 rows. The background and transparent pixels
+// are colour 0, and both palettes map colours to the same shades.
+fn expected_8x16_sprites() -> Vec<u32> {
+    let shades = DmgPalette::default().obj0;
+    let mut frame = vec![shades[0]; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+    let sprites = [(8, false, false), (24, true, false), (40, false, true), (56, true, true)];
+    for &(x, x_flip, y_flip) in sprites.iter() {
+        for row in 0..16 {
+            for column in 0..8 {
+                let tile_row = if y_flip { 15 - row } else { row };
+                let tile_column = if x_flip { 7 - column } else { column };
+                let color = pattern(tile_row, tile_column);
+                frame[(8 + row) * DISPLAY_WIDTH + x + column] = shades[color as usize]
+            }
+        }
+    }
+    frame
+}
+
+#[test]
+fn sprites_8x16_flip_reference() {
+    assert_matches_reference(&expected_8x16_sprites(), SPRITES_8X16_FLIP)
+}
+
+#[test]
+fn sprites_8x16_flip_scanline() {
+    let frame = render_frame(Renderer::Scanline, setup_8x16_sprites);
+    assert_matches_reference(&frame, SPRITES_8X16_FLIP)
+}
+
+#[test]
+fn sprites_8x16_flip_pixel_fifo() {
+    let frame = render_frame(Renderer::PixelFifo, setup_8x16_sprites);
+    assert_matches_reference(&frame, SPRITES_8X16_FLIP)
+}
+
+// LCD just turned on, with 0x11 at the start of VRAM and 0x22 in OAM
+fn lcd_on() -> Ppu {
+    let mut ppu = Ppu::new(GameboyType::Dmg);
+    ppu.set_access_locking(true);
+    ppu.write(0xff40, 0x00);
+    ppu.write(0x8000, 0x11);
+    ppu.write(0xfe00, 0x22);
+    ppu.write(0xff40, 0x91);
+    ppu
+}
+
+fn run_to_mode(ppu: &mut Ppu, mode: u8) {
+    while ppu.read(0xff41) & 0x03 != mode {
+        ppu.cycle_flush(4);
+    }
+}
+
+#[test]
+fn vram_and_oam_locked_by_mode() {
+    // Mode, then whether the CPU can access VRAM and OAM in it
+    let cases = [(2, true, false), (3, false, false), (0, true, true), (1, true, true)];
+    for &(mode, vram, oam) in cases.iter() {
+        let mut ppu = lcd_on();
+        run_to_mode(&mut ppu, mode);
+        assert_eq!(ppu.read(0x8000), if vram { 0x11 } else { 0xff }, "VRAM in mode {}", mode);
+        assert_eq!(ppu.read(0xfe00), if oam { 0x22 } else { 0xff }, "OAM in mode {}", mode);
+
+        ppu.write(0x8000, 0x33);
+        ppu.write(0xfe00, 0x44);
+        assert_eq!(ppu.peek(0x8000), if vram { 0x33 } else { 0x11 }, "VRAM in mode {}", mode);
+        assert_eq!(ppu.peek(0xfe00), if oam { 0x44 } else { 0x22 }, "OAM in mode {}", mode);
+    }
+}
+
+#[test]
+fn vram_locked_after_oam_search() {
+    let mut ppu = lcd_on();
+    ppu.cycle_flush(OAM_CYCLES - 4);
+    assert_eq!(ppu.read(0x8000), 0x11);
+    ppu.cycle_flush(4);
+    assert_eq!(ppu.read(0x8000), 0xff);
+}
+
+#[test]
+fn access_locking_disabled() {
+    let mut ppu = lcd_on();
+    ppu.set_access_locking(false);
+    run_to_mode(&mut ppu, 3);
+    ppu.write(0xfe00, 0x44);
+    assert_eq!(ppu.read(0x8000), 0x11);
+    assert_eq!(ppu.read(0xfe00), 0x44);
+}
+
+// Runs a line at a time from the start of line 1, returning the modes the
+// STAT interrupt was requested in
+fn stat_interrupt_modes(ppu: &mut Ppu, lines: usize) -> Vec<u8> {
+    while ppu.read(0xff44) != 1 {
+        ppu.cycle_flush(4);
+    }
+    let mut modes = Vec::new();
+    for _ in 0..lines * 456 / 4 {
+        if ppu.cycle_flush(4) & Interrupt::LCDStat.flag() != 0 {
+            modes.push(ppu.read(0xff41) & 0x03)
+        }
+    }
+    modes
+}
+
+#[test]
+fn stat_interrupt_on_rising_edge_only() {
+    // HBlank runs straight into OAM search, so the line only rises in HBlank
+    let mut ppu = lcd_on();
+    ppu.write(0xff41, 0x28);
+    assert_eq!(stat_interrupt_modes(&mut ppu, 4), vec![0; 4]);
+
+    // LY=LYC holds the line high through line 2, blocking its OAM and HBlank
+    // interrupts
+    let mut ppu = lcd_on();
+    ppu.write(0xff45, 2);
+    ppu.write(0xff41, 0x68);
+    assert_eq!(stat_interrupt_modes(&mut ppu, 4), vec![0, 0, 0]);
+}
+
+#[test]
+fn lyc_flag_without_its_interrupt() {
+    let mut ppu = lcd_on();
+    ppu.write(0xff45, 2);
+    ppu.write(0xff41, 0x00);
+    for line in 0..4 {
+        while ppu.read(0xff44) != line {
+            ppu.cycle_flush(4);
+        }
+        assert_eq!(ppu.read(0xff41) & 0x04 != 0, line == 2, "line {}", line);
+    }
+}
+
+#[test]
+fn ly_reads_0_early_in_line_153() {
+    let mut ppu = lcd_on();
+    while ppu.read(0xff44) != 153 {
+        ppu.cycle_flush(4);
+    }
+    ppu.cycle_flush(4);
+    assert_eq!(ppu.read(0xff44), 0);
+    assert_eq!(ppu.read(0xff41) & 0x03, 1);
+}
+
+#[test]
+fn stat_write_interrupt_on_dmg_only() {
+    for &(gameboy_type, expected) in [(GameboyType::Dmg, true), (GameboyType::Cgb, false)].iter() {
+        let mut ppu = Ppu::new(gameboy_type);
+        ppu.write(0xff40, 0x00);
+        ppu.write(0xff40, 0x91);
+        run_to_mode(&mut ppu, 0);
+        ppu.cycle_flush(0);
+        ppu.write(0xff41, 0x00);
+        let interrupt = ppu.cycle_flush(0) & Interrupt::LCDStat.flag() != 0;
+        assert_eq!(interrupt, expected, "{:?}", gameboy_type);
+    }
+}
+
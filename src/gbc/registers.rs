@@ -24,6 +24,7 @@ pub enum Reg16 {
     SP,
 }
 
+#[derive(Clone)]
 pub struct Registers {
     pub a: u8,
     pub b: u8,
@@ -69,6 +70,15 @@ impl Registers {
         }
     }
 
+    /// State at power on, before the boot ROM has run
+    pub fn power_on() -> Registers {
+        Registers {
+            sp: 0,
+            pc: 0,
+            ..Default::default()
+        }
+    }
+
     #[inline(always)]
     pub fn read_u8(&self, reg: Reg8) -> u8 {
         use self::Reg8::*;
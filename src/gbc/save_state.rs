@@ -0,0 +1,294 @@
+// Save state format: a header with a magic number, the format version, the
+// hardware type and a hash of the ROM, followed by one section per component.
+// Sections are tagged and length prefixed, so a corrupt or mismatched state
+// is reported against the component it failed in.
+
+use super::GameboyType;
+
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"GBCS";
+
+/// Current format version, bumped whenever the layout of a section changes.
+/// Older versions can still be loaded, with defaults for what they lack.
+///
+/// 2: serial transfer cycles in BUS
+/// 3: PPU frames as colour indices, without the rows still to be drawn
+pub const VERSION: u16 = 3;
+
+/// Oldest format version that can be loaded
+pub const OLDEST_VERSION: u16 = 1;
+
+pub type Tag = [u8; 4];
+
+#[derive(Debug)]
+pub enum StateError {
+    /// The data doesn't start with the save state magic number
+    NotASaveState,
+    UnsupportedVersion { found: u16, supported: u16 },
+    WrongGameboyType,
+    /// The state was saved while running a different ROM
+    WrongRom,
+    /// The state was saved while the boot ROM was running, but none is loaded
+    BootRomMissing,
+    Truncated,
+    MissingSection { expected: Tag, found: Tag },
+    SectionLength(Tag),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotASaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { found, supported } => {
+                write!(f,
+                       "save state version {} is not supported, expected version {} or older",
+                       found,
+                       supported)
+            }
+            StateError::WrongGameboyType => {
+                write!(f, "save state is for a different hardware type")
+            }
+            StateError::WrongRom => write!(f, "save state is for a different ROM"),
+            StateError::BootRomMissing => {
+                write!(f, "save state was made while running a boot ROM, which isn't loaded")
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MissingSection { expected, found } => {
+                write!(f,
+                       "expected section {}, found {}",
+                       tag_name(&expected),
+                       tag_name(&found))
+            }
+            StateError::SectionLength(tag) => {
+                write!(f, "section {} has the wrong length", tag_name(&tag))
+            }
+            StateError::Invalid(what) => write!(f, "invalid {}", what),
+        }
+    }
+}
+
+fn tag_name(tag: &Tag) -> String {
+    String::from_utf8_lossy(tag).trim_end().to_string()
+}
+
+/// FNV-1a hash, identifies ROMs in save states
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325,
+                      |hash, &b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+pub fn gameboy_type_id(gameboy_type: GameboyType) -> u8 {
+    match gameboy_type {
+        GameboyType::Dmg => 0,
+        GameboyType::Cgb => 1,
+    }
+}
+
+/// A component whose state is included in save states
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(gameboy_type: GameboyType, rom_hash: u64) -> StateWriter {
+        let mut writer = StateWriter { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(&MAGIC);
+        writer.u16(VERSION);
+        writer.u8(gameboy_type_id(gameboy_type));
+        writer.u64(rom_hash);
+        writer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Writes a section, `write` fills in its contents
+    pub fn section<F>(&mut self, tag: Tag, write: F)
+        where F: FnOnce(&mut StateWriter)
+    {
+        self.bytes.extend_from_slice(&tag);
+        let length_offset = self.bytes.len();
+        self.u32(0);
+        write(self);
+        let length = (self.bytes.len() - length_offset - 4) as u32;
+        for i in 0..4 {
+            self.bytes[length_offset + i] = (length >> (i * 8)) as u8
+        }
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.bytes.push(val)
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val as u8)
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.u8(val as u8);
+        self.u8((val >> 8) as u8)
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.u16(val as u16);
+        self.u16((val >> 16) as u16)
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.u32(val as u32);
+        self.u32((val >> 32) as u32)
+    }
+
+    /// Writes a length prefixed byte string
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes)
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    tag: Option<Tag>,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header, the state must be from a supported version and the
+    /// same hardware type and ROM
+    pub fn new(bytes: &'a [u8],
+               gameboy_type: GameboyType,
+               rom_hash: u64)
+               -> Result<StateReader<'a>, StateError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(StateError::NotASaveState);
+        }
+        let mut reader = StateReader {
+            bytes: &bytes[MAGIC.len()..],
+            tag: None,
+            version: VERSION,
+        };
+
+        let version = reader.u16()?;
+        if version < OLDEST_VERSION || version > VERSION {
+            return Err(StateError::UnsupportedVersion {
+                found: version,
+                supported: VERSION,
+            });
+        }
+        if reader.u8()? != gameboy_type_id(gameboy_type) {
+            return Err(StateError::WrongGameboyType);
+        }
+        if reader.u64()? != rom_hash {
+            return Err(StateError::WrongRom);
+        }
+        reader.version = version;
+        Ok(reader)
+    }
+
+    /// Format version of the state, for sections whose layout changed
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Reads a section written by `StateWriter::section`, `read` has to
+    /// consume all of it
+    pub fn section<F>(&mut self, tag: Tag, read: F) -> Result<(), StateError>
+        where F: FnOnce(&mut StateReader) -> Result<(), StateError>
+    {
+        let found = {
+            let bytes = self.take(4)?;
+            [bytes[0], bytes[1], bytes[2], bytes[3]]
+        };
+        if found != tag {
+            return Err(StateError::MissingSection {
+                expected: tag,
+                found: found,
+            });
+        }
+        let length = self.u32()? as usize;
+        if length > self.bytes.len() {
+            return Err(StateError::SectionLength(tag));
+        }
+        let mut section = StateReader {
+            bytes: self.take(length)?,
+            tag: Some(tag),
+            version: self.version,
+        };
+        read(&mut section)?;
+        section.finish()
+    }
+
+    /// Fails if there is data left over
+    pub fn finish(&self) -> Result<(), StateError> {
+        match (self.bytes.is_empty(), self.tag) {
+            (true, _) => Ok(()),
+            (false, Some(tag)) => Err(StateError::SectionLength(tag)),
+            (false, None) => Err(StateError::Invalid("data after the last section")),
+        }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if count > self.bytes.len() {
+            return Err(match self.tag {
+                Some(tag) => StateError::SectionLength(tag),
+                None => StateError::Truncated,
+            });
+        }
+        let (bytes, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let low = self.u16()? as u32;
+        let high = self.u16()? as u32;
+        Ok(low | high << 16)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(low | high << 32)
+    }
+
+    /// Reads a length prefixed byte string
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    /// Reads a byte string into `dest`, which it has to fit exactly
+    pub fn bytes_into(&mut self, dest: &mut [u8]) -> Result<(), StateError> {
+        let length = self.u32()? as usize;
+        if length != dest.len() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        dest.copy_from_slice(self.take(length)?);
+        Ok(())
+    }
+}
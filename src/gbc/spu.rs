@@ -1,17 +1,90 @@
+use super::CpuClock;
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
-#[derive(Debug)]
-pub struct Spu;
+/// Output sample rate, samples are interleaved stereo
+pub const SAMPLE_RATE: u32 = 44100;
+
+const REGISTER_COUNT: usize = 0x30;
+
+// Bits that always read as 1, for 0xff10-0xff3f
+const READ_MASKS: [u8; REGISTER_COUNT] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10-NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf, // NR20-NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30-NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // Unmapped
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const NR52: usize = 0x16;
+
+#[derive(Debug,Clone)]
+pub struct Spu {
+    regs: Box<[u8]>,
+    samples: Vec<i16>,
+    sample_cycles: u64,
+}
 
 impl Spu {
     pub fn new() -> Spu {
-        Spu {}
+        Spu {
+            regs: vec![0; REGISTER_COUNT].into_boxed_slice(),
+            samples: Vec::new(),
+            sample_cycles: 0,
+        }
     }
 
-    #[allow(unused_variables)]
-    pub fn write(&mut self, addr: u16, val: u8) {}
+    pub fn cycle_flush(&mut self, cycle_count: u32) {
+        // The channels aren't synthesized yet, so the output is silence at the
+        // right rate
+        let clock = CpuClock::Normal.value() as u64;
+        self.sample_cycles += cycle_count as u64 * SAMPLE_RATE as u64;
+        while self.sample_cycles >= clock {
+            self.sample_cycles -= clock;
+            self.samples.push(0);
+            self.samples.push(0)
+        }
+    }
+
+    /// Samples produced since the last call to `clear_samples`
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn clear_samples(&mut self) {
+        self.samples.clear()
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        let index = (addr - 0xff10) as usize;
+        self.regs[index] = match index {
+            // Channel status bits are read only
+            NR52 => val & 0x80,
+            _ => val,
+        }
+    }
 
-    #[allow(unused_variables)]
     pub fn read(&self, addr: u16) -> u8 {
-        0
+        let index = (addr - 0xff10) as usize;
+        READ_MASKS[index] | self.regs[index]
+    }
+}
+
+impl SaveState for Spu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"SPU ", |w| {
+            w.bytes(&self.regs);
+            w.u64(self.sample_cycles)
+        })
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"SPU ", |r| {
+            r.bytes_into(&mut self.regs)?;
+            self.sample_cycles = r.u64()?;
+            Ok(())
+        })
     }
 }
@@ -1,5 +1,7 @@
 use std::u8;
+use std::u32;
 use super::Interrupt;
+use super::save_state::{SaveState, StateWriter, StateReader, StateError};
 
 #[allow(dead_code)]
 const DIV_INC_RATE_0: u32 = 16384;
@@ -9,7 +11,7 @@ const DIV_INC_RATE_1: u32 = 32768;
 
 const CLOCKS: [u32; 4] = [1024, 16, 64, 256];
 
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub struct Timer {
     div: u8,
     div_cycles: u8,
@@ -40,7 +42,7 @@ impl Timer {
             0xff04 => self.div,
             0xff05 => self.tima,
             0xff06 => self.tma,
-            0xff07 => (self.clock_select & 0b11) | if self.enabled { 0b100 } else { 0 },
+            0xff07 => 0xf8 | (self.clock_select & 0b11) | if self.enabled { 0b100 } else { 0 },
 
             _ => panic!("Address not in range 0x{:x}", addr),
         }
@@ -71,6 +73,14 @@ impl Timer {
         }
     }
 
+    pub fn cycles_until_event(&self) -> u32 {
+        if self.enabled {
+            (0x100 - self.tima as u32) * self.clock_rate - self.tima_cycles
+        } else {
+            u32::MAX
+        }
+    }
+
     fn flush_tima(&mut self, cycle_count: u32) -> bool {
         let tima_cycles = self.tima_cycles + cycle_count;
         let rate = self.clock_rate;
@@ -106,3 +116,29 @@ impl Timer {
         }
     }
 }
+
+impl SaveState for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.section(*b"TIMR", |w| {
+            w.u8(self.div);
+            w.u8(self.div_cycles);
+            w.u8(self.tima);
+            w.u32(self.tima_cycles);
+            w.u8(self.tma);
+            w.u8(self.read(0xff07))
+        })
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.section(*b"TIMR", |r| {
+            self.div = r.u8()?;
+            self.div_cycles = r.u8()?;
+            self.tima = r.u8()?;
+            self.tima_cycles = r.u32()?;
+            self.tma = r.u8()?;
+            let tac = r.u8()?;
+            self.write(0xff07, tac);
+            Ok(())
+        })
+    }
+}
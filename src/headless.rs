@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use gbc_rs::{Emulator, DmgPalette, Runner, Condition, Outcome, SCREEN_WIDTH, SCREEN_HEIGHT};
+use gbc_rs::png;
+
+use super::{load_bin, options, start_playback, usage_error};
+
+const DEFAULT_FRAMES: u32 = 3600;
+
+struct Args {
+    rom: PathBuf,
+    boot_rom: Option<String>,
+    frames: Option<u32>,
+    input: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    dump_serial: bool,
+    canonical_palette: bool,
+    conditions: Vec<Condition>,
+}
+
+fn parse_args(args: &[String]) -> Args {
+    let mut paths = Vec::new();
+    let mut parsed = Args {
+        rom: PathBuf::new(),
+        boot_rom: None,
+        frames: None,
+        input: None,
+        screenshot: None,
+        dump_serial: false,
+        canonical_palette: false,
+        conditions: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .unwrap_or_else(|| usage_error(&format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--headless" => (),
+            "--frames" => {
+                let frames = value();
+                parsed.frames = Some(frames.parse()
+                    .unwrap_or_else(|_| usage_error(&format!("Invalid frame count {}", frames))))
+            }
+            "--input" => parsed.input = Some(PathBuf::from(value())),
+            "--screenshot" => parsed.screenshot = Some(PathBuf::from(value())),
+            "--dump-serial" => parsed.dump_serial = true,
+            "--canonical-palette" => parsed.canonical_palette = true,
+            "--pass-serial" => parsed.conditions.push(serial(value(), Outcome::Passed)),
+            "--fail-serial" => parsed.conditions.push(serial(value(), Outcome::Failed)),
+            "--pass-memory" => parsed.conditions.push(memory(&value(), Outcome::Passed)),
+            "--fail-memory" => parsed.conditions.push(memory(&value(), Outcome::Failed)),
+            "--mooneye" => parsed.conditions.push(Condition::Mooneye),
+            "--blargg" => parsed.conditions.push(Condition::Blargg),
+            option if option.starts_with("--") => {
+                usage_error(&format!("Unknown option {}", option))
+            }
+            path => paths.push(path.to_string()),
+        }
+    }
+
+    match paths.len() {
+        1 | 2 => {
+            parsed.rom = PathBuf::from(&paths[0]);
+            parsed.boot_rom = paths.get(1).cloned()
+        }
+        0 => usage_error("No ROM given"),
+        _ => usage_error("Too many arguments"),
+    }
+    parsed
+}
+
+fn serial(text: String, outcome: Outcome) -> Condition {
+    Condition::Serial {
+        text: text,
+        outcome: outcome,
+    }
+}
+
+// ADDR=VALUE, both in hex
+fn memory(arg: &str, outcome: Outcome) -> Condition {
+    let mut parts = arg.splitn(2, '=');
+    let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+    let value = parts.next().and_then(|value| u8::from_str_radix(value, 16).ok());
+    match (addr, value) {
+        (Some(addr), Some(value)) => {
+            Condition::Memory {
+                addr: addr,
+                value: value,
+                outcome: outcome,
+            }
+        }
+        _ => usage_error(&format!("Invalid memory condition {}, expected ADDR=VALUE", arg)),
+    }
+}
+
+/// Runs without a window or frame pacing, returns the exit status
+pub fn run(args: &[String]) -> i32 {
+    let args = parse_args(args);
+    let rom = match load_bin(&args.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: {}", args.rom.display(), e);
+            return 3;
+        }
+    };
+    let mut options = match options(args.boot_rom.as_ref()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 3;
+        }
+    };
+    if args.canonical_palette {
+        options.dmg_palette = DmgPalette::canonical()
+    }
+
+    let (emulator, input) = match args.input {
+        Some(ref path) => {
+            match start_playback(path, rom, options) {
+                Ok((emulator, movie)) => (emulator, movie.frames().to_vec()),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    return 3;
+                }
+            }
+        }
+        None => {
+            match Emulator::new(rom, options) {
+                Ok(emulator) => (emulator, Vec::new()),
+                Err(e) => {
+                    eprintln!("{}: {}", args.rom.display(), e);
+                    return 3;
+                }
+            }
+        }
+    };
+    let frames = args.frames.unwrap_or(if input.is_empty() {
+        DEFAULT_FRAMES
+    } else {
+        input.len() as u32
+    });
+
+    let mut runner = Runner::new(emulator);
+    runner.emulator_mut().set_serial_capture(args.dump_serial);
+    runner.set_input(&input);
+    for condition in args.conditions {
+        runner.add_condition(condition)
+    }
+    let outcome = runner.run(frames);
+    eprintln!("{} after {} frames", outcome, runner.frame());
+
+    let emulator = runner.emulator();
+    if args.dump_serial {
+        println!("{}", String::from_utf8_lossy(emulator.serial_output()))
+    }
+    if let Some(path) = args.screenshot {
+        let png = png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, emulator.framebuffer());
+        if let Err(e) = File::create(&path).and_then(|mut file| file.write_all(&png)) {
+            eprintln!("Failed to write screenshot to {}: {}", path.display(), e);
+            return 3;
+        }
+    }
+    outcome.exit_code()
+}
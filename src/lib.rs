@@ -0,0 +1,27 @@
+//! Game Boy and Game Boy Color emulator core.
+//!
+//! `Emulator` runs a cartridge and is all a frontend needs, the `gbc` module
+//! exposes the individual components.
+
+pub mod gbc;
+pub mod png;
+
+mod emulator;
+mod channel;
+mod rewind;
+mod movie;
+mod runner;
+
+#[cfg(test)]
+mod fixtures;
+
+pub use emulator::{Emulator, Options, LoadError, SCREEN_WIDTH, SCREEN_HEIGHT, CYCLES_PER_FRAME};
+pub use channel::ChannelAdaptor;
+pub use rewind::Rewind;
+pub use movie::{Movie, MovieError};
+pub use runner::{Runner, Condition, Outcome};
+pub use gbc::GameboyType;
+pub use gbc::gamepad::{Button, ButtonState, InputEvent};
+pub use gbc::palette::{DmgPalette, Preset};
+pub use gbc::ppu::Renderer;
+pub use gbc::save_state::StateError;
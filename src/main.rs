@@ -1,128 +1,144 @@
+extern crate gbc_rs;
+#[cfg(feature = "frontend")]
 extern crate minifb;
 
-use minifb::{Key, WindowOptions, Window};
+#[cfg(feature = "frontend")]
+mod window;
+mod headless;
 
 use std::env;
 use std::path::PathBuf;
 use std::boxed::Box;
 use std::fs::File;
-use std::io::Read;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
-
-mod gbc;
-
-use gbc::cart::Cart;
-use gbc::cpu::Cpu;
-use gbc::ppu::Ppu;
-use gbc::spu::Spu;
-use gbc::gamepad::{Gamepad, Button, ButtonState, InputEvent};
-use gbc::interconnect::Interconnect;
-
-fn load_bin(path: &PathBuf) -> Box<[u8]> {
+use std::io::{self, Read};
+use std::process;
+
+use gbc_rs::{Emulator, Options, Movie, DmgPalette, Renderer};
+
+const USAGE: &'static str = "usage: gbc_rs [run [--headless] [options]] rom.gb [boot_rom.bin]
+
+Headless options:
+    --frames N              stop after N frames, by default the length of the
+                            input movie or 3600
+    --input PATH            play back a movie, or a BizHawk Input Log.txt
+    --screenshot PATH       write the last frame to a PNG file
+    --dump-serial           print the serial output when the run ends
+    --canonical-palette     use the DMG shades of test ROM reference screenshots
+    --pass-serial TEXT      pass once the serial output contains TEXT
+    --fail-serial TEXT      fail once the serial output contains TEXT
+    --pass-memory ADDR=VAL  pass once memory at ADDR holds VAL, both in hex
+    --fail-memory ADDR=VAL  fail once memory at ADDR holds VAL, both in hex
+    --mooneye               pass or fail on the mooneye register signature
+    --blargg                pass or fail on the blargg result in cartridge RAM
+
+Exits with 0 when passed, 1 when failed, 2 when no condition was met and 3 on
+errors.";
+
+fn load_bin(path: &PathBuf) -> io::Result<Box<[u8]>> {
     let mut bytes = Vec::new();
-    let mut file = File::open(path).unwrap();
-    file.read_to_end(&mut bytes).unwrap();
-    bytes.into_boxed_slice()
+    File::open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes.into_boxed_slice())
 }
 
-fn keycode_to_button(keycode: Key) -> Option<Button> {
-    match keycode {
-        Key::Space => Some(Button::A),
-        Key::LeftCtrl => Some(Button::B),
-        Key::Enter => Some(Button::Start),
-        Key::RightShift => Some(Button::Select),
-        Key::Up => Some(Button::Up),
-        Key::Down => Some(Button::Down),
-        Key::Left => Some(Button::Left),
-        Key::Right => Some(Button::Right),
-        _ => None,
+fn load_palette() -> DmgPalette {
+    let path = match env::var_os("GBC_PALETTE") {
+        Some(path) => PathBuf::from(path),
+        None => return DmgPalette::default(),
+    };
+    let config = match load_bin(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            println!("Failed to read palette config {}: {}", path.display(), e);
+            return DmgPalette::default();
+        }
+    };
+    match DmgPalette::from_config(&config) {
+        Ok(palette) => palette,
+        Err(e) => {
+            println!("Invalid palette config {}: {}", path.display(), e);
+            DmgPalette::default()
+        }
     }
 }
 
-fn make_events(current: Vec<Key>, prev: Vec<Key>) -> Vec<InputEvent> {
-
-    let released: Vec<_> = prev.clone().into_iter().filter(|x| !current.contains(x)).collect();
-    let pressed: Vec<_> = current.into_iter().filter(|x| !prev.contains(x)).collect();
-
-    let mut events = Vec::new();
-
-    for r in released {
-        if let Some(button) = keycode_to_button(r) {
-            events.push(InputEvent::new(button, ButtonState::Up))
-        }
+fn options(boot_rom: Option<&String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    if let Some(path) = boot_rom {
+        let bytes = load_bin(&PathBuf::from(path)).map_err(|e| format!("{}: {}", path, e))?;
+        options.boot_rom = Some(bytes)
     }
-
-    for p in pressed {
-        if let Some(button) = keycode_to_button(p) {
-            events.push(InputEvent::new(button, ButtonState::Down))
-        }
+    options.dmg_palette = load_palette();
+    // Lets the CPU access VRAM and OAM in every PPU mode, for debugging
+    if env::var_os("GBC_NO_ACCESS_LOCKING").is_some() {
+        options.access_locking = false
+    }
+    if env::var("GBC_RENDERER").ok().map_or(false, |renderer| renderer == "fifo") {
+        options.renderer = Renderer::PixelFifo
     }
-    events
+    Ok(options)
 }
 
-fn main() {
-    let rom_path = PathBuf::from(env::args().nth(1).unwrap());
-    let rom_binary = load_bin(&rom_path);
-
-    let cart = Cart::new(rom_binary);
-
-    println!("{:?}", cart);
-
-    // let gb_type = cart.gameboy_type();
-    let gb_type = gbc::GameboyType::Dmg;
-
-    let (tx, rx): (Sender<Box<[u32]>>, Receiver<Box<[u32]>>) = mpsc::channel();
-    let (gamepad_tx, gamepad_rx): (Sender<InputEvent>, Receiver<InputEvent>) = mpsc::channel();
-
-    let ppu = Ppu::new(tx.clone());
-    let spu = Spu::new();
-    let gamepad = Gamepad::new(gamepad_rx);
-    let interconnect = Interconnect::new(gb_type, cart, ppu, spu, gamepad);
-
-    let mut cpu = Cpu::new(gb_type, interconnect);
-
-    let mut window = Window::new("GBC_RS",
-                                 160,
-                                 144,
-                                 WindowOptions { scale: minifb::Scale::X4, ..Default::default() })
-        .unwrap_or_else(|e| panic!("{}", e));
-
-    let sleep_time = std::time::Duration::from_millis(16);
-
-    let mut prev_keys = Vec::new();
+// Takes a movie recorded with GBC_RECORD, or the Input Log.txt extracted from
+// a BizHawk .bk2 movie
+fn start_playback(path: &PathBuf,
+                  rom: Box<[u8]>,
+                  options: Options)
+                  -> Result<(Emulator, Movie), String> {
+    let bytes = load_bin(path).map_err(|e| e.to_string())?;
+    if path.extension().map_or(false, |extension| extension == "txt") {
+        let emulator = Emulator::new(rom, options).map_err(|e| e.to_string())?;
+        let movie = Movie::from_bk2_input_log(&String::from_utf8_lossy(&bytes), &emulator)
+            .map_err(|e| e.to_string())?;
+        Ok((emulator, movie))
+    } else {
+        let movie = Movie::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        let palette = options.dmg_palette;
+        let mut emulator = movie.emulator(rom, options.boot_rom).map_err(|e| e.to_string())?;
+        emulator.set_dmg_palette(palette);
+        Ok((emulator, movie))
+    }
+}
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(3)
+}
 
-        let now = std::time::Instant::now();
+#[cfg(feature = "frontend")]
+fn run_window(args: &[String]) {
+    let rom_path = PathBuf::from(&args[0]);
+    let rom_binary = load_bin(&rom_path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_path.display(), e);
+        process::exit(3)
+    });
+    let options = options(args.get(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(3)
+    });
+    window::run(&rom_path, rom_binary, options)
+}
 
-        let mut cycle_count: u32 = 0;
+#[cfg(not(feature = "frontend"))]
+fn run_window(_args: &[String]) {
+    eprintln!("Built without the window frontend, use run --headless");
+    process::exit(3)
+}
 
-        loop {
-            cycle_count += cpu.step() as u32;
-            if cycle_count >= 70224 {
-                break;
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        None => usage_error("No ROM given"),
+        Some("run") => {
+            let args = &args[1..];
+            if args.iter().any(|arg| arg == "--headless") {
+                process::exit(headless::run(args))
+            }
+            match args.len() {
+                1 | 2 => run_window(args),
+                _ => usage_error("Options other than the ROM paths need --headless"),
             }
         }
-
-        if let Ok(framebuffer) = rx.try_recv() {
-            window.update_with_buffer(&framebuffer)
-        } else {
-            window.update()
-        }
-
-        if let Some(keys) = window.get_keys() {
-            make_events(keys.clone(), prev_keys)
-                .into_iter()
-                .map(|e| gamepad_tx.send(e).unwrap())
-                .collect::<Vec<_>>();
-            prev_keys = keys
-        }
-
-        let elapsed = now.elapsed();
-        if sleep_time > elapsed {
-            let sleep = sleep_time - elapsed;
-            std::thread::sleep(sleep)
-        }
+        Some(_) if args.len() <= 2 => run_window(&args),
+        Some(_) => usage_error("Too many arguments"),
     }
 }
@@ -0,0 +1,474 @@
+// Movie format: a header with a magic number, the format version and the
+// settings the emulator ran with, an optional save state the recording starts
+// from, and one button mask per frame. Without a save state the recording
+// starts at power on.
+
+use emulator::{Emulator, Options, LoadError};
+use gbc::GameboyType;
+use gbc::gamepad::Button;
+use gbc::ppu::Renderer;
+use gbc::save_state::{self, StateError};
+
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"GBCM";
+
+/// Current format version, bumped whenever the layout changes
+pub const VERSION: u16 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// The data doesn't start with the movie magic number
+    NotAMovie,
+    UnsupportedVersion { found: u16, supported: u16 },
+    Truncated,
+    /// The movie was recorded while running a different ROM
+    WrongRom,
+    /// The movie was recorded with a boot ROM, but none or a different one
+    /// was given
+    WrongBootRom,
+    /// The emulator couldn't be created from the ROMs
+    Load(LoadError),
+    /// The save state the movie starts from failed to load
+    State(StateError),
+    Invalid(&'static str),
+    /// A BizHawk input log couldn't be parsed
+    Bk2(String),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion { found, supported } => {
+                write!(f,
+                       "movie version {} is not supported, expected version {}",
+                       found,
+                       supported)
+            }
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::WrongRom => write!(f, "movie is for a different ROM"),
+            MovieError::WrongBootRom => write!(f, "movie was recorded with a different boot ROM"),
+            MovieError::Load(ref e) => write!(f, "{}", e),
+            MovieError::State(ref e) => write!(f, "movie start state: {}", e),
+            MovieError::Invalid(what) => write!(f, "invalid {}", what),
+            MovieError::Bk2(ref e) => write!(f, "invalid BizHawk input log: {}", e),
+        }
+    }
+}
+
+/// Joypad input for every frame of a run, replayed with the same settings
+/// it reproduces the run exactly
+#[derive(Debug,Clone)]
+pub struct Movie {
+    gameboy_type: GameboyType,
+    rom_hash: u64,
+    boot_rom_hash: Option<u64>,
+    renderer: Renderer,
+    access_locking: bool,
+    start_state: Option<Vec<u8>>,
+    frames: Vec<u8>,
+}
+
+impl Movie {
+    /// Starts a recording at power on, the emulator must not have run yet
+    pub fn power_on(emulator: &Emulator) -> Movie {
+        Movie {
+            gameboy_type: emulator.gameboy_type(),
+            rom_hash: emulator.cart().hash(),
+            boot_rom_hash: emulator.boot_rom_hash(),
+            renderer: emulator.renderer(),
+            access_locking: emulator.access_locking(),
+            start_state: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Starts a recording from the current state of the emulator
+    pub fn from_state(emulator: &Emulator) -> Movie {
+        Movie { start_state: Some(emulator.save_state()), ..Movie::power_on(emulator) }
+    }
+
+    /// Adds a frame, `buttons` being the button mask the frame runs with
+    pub fn record(&mut self, buttons: u8) {
+        self.frames.push(buttons)
+    }
+
+    /// Button mask of every frame, in order
+    pub fn frames(&self) -> &[u8] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Creates an emulator with the recorded settings, at the point the
+    /// recording starts. Running it with `frames` as input replays the movie.
+    pub fn emulator(&self,
+                    rom: Box<[u8]>,
+                    boot_rom: Option<Box<[u8]>>)
+                    -> Result<Emulator, MovieError> {
+        if save_state::hash(&rom) != self.rom_hash {
+            return Err(MovieError::WrongRom);
+        }
+        if boot_rom.as_ref().map(|boot_rom| save_state::hash(boot_rom)) != self.boot_rom_hash {
+            return Err(MovieError::WrongBootRom);
+        }
+
+        let mut options = Options::default();
+        options.gameboy_type = self.gameboy_type;
+        options.boot_rom = boot_rom;
+        options.renderer = self.renderer;
+        options.access_locking = self.access_locking;
+
+        let mut emulator = Emulator::new(rom, options).map_err(MovieError::Load)?;
+        if let Some(ref state) = self.start_state {
+            emulator.load_state(state).map_err(MovieError::State)?
+        }
+        Ok(emulator)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        push_u16(&mut bytes, VERSION);
+        bytes.push(save_state::gameboy_type_id(self.gameboy_type));
+        push_u64(&mut bytes, self.rom_hash);
+        bytes.push(self.boot_rom_hash.is_some() as u8);
+        push_u64(&mut bytes, self.boot_rom_hash.unwrap_or(0));
+        bytes.push(match self.renderer {
+            Renderer::Scanline => 0,
+            Renderer::PixelFifo => 1,
+        });
+        bytes.push(self.access_locking as u8);
+        bytes.push(self.start_state.is_some() as u8);
+        if let Some(ref state) = self.start_state {
+            push_u32(&mut bytes, state.len() as u32);
+            bytes.extend_from_slice(state)
+        }
+        push_u32(&mut bytes, self.frames.len() as u32);
+        bytes.extend_from_slice(&self.frames);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let mut reader = Reader { bytes: &bytes[MAGIC.len()..] };
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion {
+                found: version,
+                supported: VERSION,
+            });
+        }
+        let gameboy_type = match reader.u8()? {
+            0 => GameboyType::Dmg,
+            1 => GameboyType::Cgb,
+            _ => return Err(MovieError::Invalid("hardware type")),
+        };
+        let rom_hash = reader.u64()?;
+        let has_boot_rom = reader.bool()?;
+        let boot_rom_hash = reader.u64()?;
+        let renderer = match reader.u8()? {
+            0 => Renderer::Scanline,
+            1 => Renderer::PixelFifo,
+            _ => return Err(MovieError::Invalid("renderer")),
+        };
+        let access_locking = reader.bool()?;
+        let start_state = if reader.bool()? {
+            let length = reader.u32()? as usize;
+            Some(reader.take(length)?.to_vec())
+        } else {
+            None
+        };
+        let frame_count = reader.u32()? as usize;
+        let frames = reader.take(frame_count)?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(MovieError::Invalid("data after the last frame"));
+        }
+
+        Ok(Movie {
+            gameboy_type: gameboy_type,
+            rom_hash: rom_hash,
+            boot_rom_hash: if has_boot_rom { Some(boot_rom_hash) } else { None },
+            renderer: renderer,
+            access_locking: access_locking,
+            start_state: start_state,
+            frames: frames,
+        })
+    }
+
+    /// Imports the `Input Log.txt` of a BizHawk .bk2 movie (which is a zip
+    /// file), as a power on recording with the settings of `emulator`
+    pub fn from_bk2_input_log(input_log: &str, emulator: &Emulator) -> Result<Movie, MovieError> {
+        let mut movie = Movie::power_on(emulator);
+        let mut columns = None;
+
+        for line in input_log.lines().map(|line| line.trim()) {
+            if line.starts_with("LogKey:") {
+                // LogKey:#P1 Up|P1 Down|...|Power|
+                let keys = line["LogKey:".len()..].split(|c| c == '#' || c == '|');
+                columns = Some(keys.filter(|key| !key.is_empty())
+                    .map(bk2_button)
+                    .collect::<Vec<_>>());
+            } else if line.starts_with('|') {
+                let columns = match columns {
+                    Some(ref columns) => columns,
+                    None => return Err(MovieError::Bk2("frame before LogKey".to_string())),
+                };
+                let inputs: Vec<char> = line.chars().filter(|&c| c != '|').collect();
+                if inputs.len() != columns.len() {
+                    return Err(MovieError::Bk2(format!("frame {} has {} inputs, expected {}",
+                                                       movie.len(),
+                                                       inputs.len(),
+                                                       columns.len())));
+                }
+                let buttons = columns.iter()
+                    .zip(inputs.iter())
+                    .filter(|&(_, &input)| input != '.' && input != ' ')
+                    .fold(0, |buttons, (button, _)| {
+                        buttons | button.map_or(0, |button| button.mask())
+                    });
+                movie.record(buttons)
+            }
+        }
+
+        match columns {
+            Some(_) => Ok(movie),
+            None => Err(MovieError::Bk2("no LogKey line".to_string())),
+        }
+    }
+}
+
+// Inputs other than buttons, like Power, are ignored
+fn bk2_button(key: &str) -> Option<Button> {
+    let name = if key.starts_with("P1 ") { &key[3..] } else { key };
+    match name {
+        "Up" => Some(Button::Up),
+        "Down" => Some(Button::Down),
+        "Left" => Some(Button::Left),
+        "Right" => Some(Button::Right),
+        "A" => Some(Button::A),
+        "B" => Some(Button::B),
+        "Start" => Some(Button::Start),
+        "Select" => Some(Button::Select),
+        _ => None,
+    }
+}
+
+fn push_u16(bytes: &mut Vec<u8>, val: u16) {
+    bytes.push(val as u8);
+    bytes.push((val >> 8) as u8)
+}
+
+fn push_u32(bytes: &mut Vec<u8>, val: u32) {
+    push_u16(bytes, val as u16);
+    push_u16(bytes, (val >> 16) as u16)
+}
+
+fn push_u64(bytes: &mut Vec<u8>, val: u64) {
+    push_u32(bytes, val as u32);
+    push_u32(bytes, (val >> 32) as u32)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MovieError> {
+        if count > self.bytes.len() {
+            return Err(MovieError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MovieError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, MovieError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(MovieError::Invalid("boolean")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, MovieError> {
+        let bytes = self.take(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    fn u32(&mut self) -> Result<u32, MovieError> {
+        let low = self.u16()? as u32;
+        let high = self.u16()? as u32;
+        Ok(low | high << 16)
+    }
+
+    fn u64(&mut self) -> Result<u64, MovieError> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(low | high << 32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieError, MAGIC};
+    use emulator::{Emulator, Options};
+    use gbc::gamepad::Button;
+    use fixtures::test_rom;
+
+    fn emulator() -> Emulator {
+        Emulator::new(test_rom(0), Options::default()).unwrap()
+    }
+
+    // Reads both halves of the joypad in a loop, keeping the last 256 reads
+    // at 0xc000 and their sum in D
+    fn joypad_rom() -> Box<[u8]> {
+        let mut rom = test_rom(0);
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        let mut program = vec![0x21, 0x00, 0xc0];
+        for &select in [0x20, 0x10].iter() {
+            program.extend_from_slice(&[0x3e, select, 0xe0, 0x00, 0xf0, 0x00]);
+            program.extend_from_slice(&[0x77, 0x2c, 0x82, 0x57])
+        }
+        let back = 2 + program.len() - 3;
+        program.extend_from_slice(&[0x18, (back as u8).wrapping_neg()]);
+        rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    fn bk2_error(input_log: &str) -> String {
+        match Movie::from_bk2_input_log(input_log, &emulator()) {
+            Err(MovieError::Bk2(e)) => e,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut emulator = emulator();
+        emulator.run_frame();
+        let movies = [Movie::power_on(&emulator), Movie::from_state(&emulator)];
+
+        for movie in movies.iter() {
+            let mut movie = movie.clone();
+            for &buttons in [0, Button::A.mask(), Button::A.mask() | Button::Up.mask()].iter() {
+                movie.record(buttons)
+            }
+            let bytes = movie.to_bytes();
+            let loaded = Movie::from_bytes(&bytes).unwrap();
+            assert_eq!(loaded.frames(), movie.frames());
+            assert_eq!(loaded.to_bytes(), bytes);
+        }
+
+        let movie = Movie::from_state(&emulator);
+        let replay = movie.emulator(test_rom(0), None).unwrap();
+        assert_eq!(replay.save_state(), emulator.save_state());
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let inputs = [0,
+                      Button::A.mask(),
+                      Button::A.mask() | Button::Up.mask(),
+                      Button::Start.mask(),
+                      Button::Down.mask() | Button::B.mask()];
+        let mut emulator = Emulator::new(joypad_rom(), Options::default()).unwrap();
+        let power_on = Movie::power_on(&emulator);
+        for _ in 0..3 {
+            emulator.run_frame();
+        }
+
+        for movie in [power_on, Movie::from_state(&emulator)].iter() {
+            let mut emulator = movie.emulator(joypad_rom(), None).unwrap();
+            let mut idle = movie.emulator(joypad_rom(), None).unwrap();
+            let mut movie = movie.clone();
+            for frame in 0..20 {
+                emulator.set_buttons(inputs[frame % inputs.len()]);
+                movie.record(emulator.buttons());
+                emulator.run_frame();
+                idle.run_frame();
+            }
+
+            let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+            let mut replay = movie.emulator(joypad_rom(), None).unwrap();
+            for &buttons in movie.frames() {
+                replay.set_buttons(buttons);
+                replay.run_frame();
+            }
+            assert_eq!(replay.save_state(), emulator.save_state());
+            // The input made a difference
+            assert!(idle.save_state() != emulator.save_state());
+        }
+    }
+
+    #[test]
+    fn malformed_movies() {
+        let bytes = Movie::power_on(&emulator()).to_bytes();
+
+        match Movie::from_bytes(b"GBCS") {
+            Err(MovieError::NotAMovie) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = 0xff;
+        match Movie::from_bytes(&future) {
+            Err(MovieError::UnsupportedVersion { found: 0xff, .. }) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match Movie::from_bytes(&bytes[..bytes.len() - 1]) {
+            Err(MovieError::Truncated) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        match Movie::from_bytes(&trailing) {
+            Err(MovieError::Invalid(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let mut other_rom = test_rom(0);
+        other_rom[0] = 1;
+        match Movie::from_bytes(&bytes).unwrap().emulator(other_rom, None) {
+            Err(MovieError::WrongRom) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn bk2_input_log() {
+        let input_log = "[Input]\n\
+                         LogKey:#P1 Up|P1 Down|P1 Left|P1 Right|\
+                         P1 Start|P1 Select|P1 B|P1 A|Power|\n\
+                         |........|.|\n\
+                         |U......A|.|\n\
+                         |...RS...|P|\n\
+                         [/Input]\n";
+        let movie = Movie::from_bk2_input_log(input_log, &emulator()).unwrap();
+        assert_eq!(movie.frames(),
+                   &[0,
+                     Button::Up.mask() | Button::A.mask(),
+                     Button::Right.mask() | Button::Start.mask()]);
+    }
+
+    #[test]
+    fn malformed_bk2_input_logs() {
+        assert_eq!(bk2_error("|U.|\nLogKey:#P1 Up|P1 Down|\n"), "frame before LogKey");
+        assert_eq!(bk2_error("LogKey:#P1 Up|P1 Down|\n|..|\n|U|\n"),
+                   "frame 1 has 1 inputs, expected 2");
+        assert_eq!(bk2_error("[Input]\n|..|\n"), "frame before LogKey");
+        assert_eq!(bk2_error("[Input]\n[/Input]\n"), "no LogKey line");
+    }
+}
@@ -0,0 +1,627 @@
+//! Minimal PNG support for screenshots, without any dependencies.
+//!
+//! Images are written as 8 bit RGB. The image data is stored without
+//! compression, which keeps the encoder small, screenshots are only about
+//! 70 KB. Any non-interlaced image can be read, for comparing screenshots
+//! with references.
+
+use std::fmt;
+
+/// Encodes pixels given as 0xRRGGBB, row by row, as a PNG file
+pub fn encode(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+
+    // Every row starts with the filter type, 0 for none
+    let mut data = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks(width) {
+        data.push(0);
+        for &pixel in row {
+            data.push((pixel >> 16) as u8);
+            data.push((pixel >> 8) as u8);
+            data.push(pixel as u8)
+        }
+    }
+
+    let mut header = Vec::new();
+    push_u32(&mut header, width as u32);
+    push_u32(&mut header, height as u32);
+    // Bit depth, colour type RGB, compression, filter and interlace method
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib_stored(&data));
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+fn push_u32(bytes: &mut Vec<u8>, val: u32) {
+    bytes.push((val >> 24) as u8);
+    bytes.push((val >> 16) as u8);
+    bytes.push((val >> 8) as u8);
+    bytes.push(val as u8)
+}
+
+fn push_chunk(png: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    push_u32(png, data.len() as u32);
+    let start = png.len();
+    png.extend_from_slice(tag);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    push_u32(png, crc)
+}
+
+// A zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+
+    let mut blocks: Vec<&[u8]> = data.chunks(MAX_BLOCK).collect();
+    if blocks.is_empty() {
+        blocks.push(&[])
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    for (index, block) in blocks.iter().enumerate() {
+        // Final block flag, block type 0 for stored
+        zlib.push((index + 1 == blocks.len()) as u8);
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&[length as u8, (length >> 8) as u8]);
+        zlib.extend_from_slice(&[!length as u8, (!length >> 8) as u8]);
+        zlib.extend_from_slice(block)
+    }
+    push_u32(&mut zlib, adler32(data));
+    zlib
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| {
+        (0..8).fold(crc ^ b as u32,
+                    |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1, 0), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    NotAPng,
+    Truncated,
+    Unsupported(&'static str),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::NotAPng => write!(f, "not a PNG file"),
+            DecodeError::Truncated => write!(f, "PNG file is truncated"),
+            DecodeError::Unsupported(what) => write!(f, "unsupported PNG {}", what),
+            DecodeError::Invalid(what) => write!(f, "invalid PNG {}", what),
+        }
+    }
+}
+
+/// A decoded image, with pixels as 0xRRGGBB row by row. Transparency is
+/// ignored.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+pub fn decode(png: &[u8]) -> Result<Image, DecodeError> {
+    if png.len() < SIGNATURE.len() || png[..SIGNATURE.len()] != SIGNATURE {
+        return Err(DecodeError::NotAPng);
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut zlib = Vec::new();
+
+    let mut rest = &png[SIGNATURE.len()..];
+    loop {
+        if rest.len() < 12 {
+            return Err(DecodeError::Truncated);
+        }
+        let length = read_u32(rest) as usize;
+        if length > rest.len() - 12 {
+            return Err(DecodeError::Truncated);
+        }
+        let tag = &rest[4..8];
+        let data = &rest[8..8 + length];
+        rest = &rest[length + 12..];
+
+        match tag {
+            b"IHDR" => header = Some(Header::parse(data)?),
+            b"PLTE" => {
+                palette = data.chunks(3)
+                    .filter(|rgb| rgb.len() == 3)
+                    .map(|rgb| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32)
+                    .collect()
+            }
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => (),
+        }
+    }
+
+    let header = header.ok_or(DecodeError::Invalid("header"))?;
+    if zlib.len() < 2 {
+        return Err(DecodeError::Truncated);
+    }
+    // Skips the zlib header, the checksum at the end isn't checked
+    let data = inflate(&zlib[2..])?;
+    let data = unfilter(&header, &data)?;
+
+    let mut pixels = Vec::with_capacity(header.width * header.height);
+    for row in data.chunks(header.row_length) {
+        for x in 0..header.width {
+            pixels.push(header.pixel(row, x, &palette)?)
+        }
+    }
+    Ok(Image {
+        width: header.width,
+        height: header.height,
+        pixels: pixels,
+    })
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+const GREYSCALE: u8 = 0;
+const RGB: u8 = 2;
+const INDEXED: u8 = 3;
+const GREYSCALE_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    colour_type: u8,
+    // Bytes in a row, without the filter type byte
+    row_length: usize,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header, DecodeError> {
+        if data.len() != 13 {
+            return Err(DecodeError::Invalid("header"));
+        }
+        let mut header = Header {
+            width: read_u32(data) as usize,
+            height: read_u32(&data[4..]) as usize,
+            bit_depth: data[8] as usize,
+            colour_type: data[9],
+            row_length: 0,
+        };
+        let bit_depth_valid = match (header.colour_type, header.bit_depth) {
+            (GREYSCALE, 1) | (GREYSCALE, 2) | (GREYSCALE, 4) => true,
+            (INDEXED, 1) | (INDEXED, 2) | (INDEXED, 4) | (INDEXED, 8) => true,
+            (GREYSCALE, 8) | (GREYSCALE, 16) => true,
+            (RGB, 8) | (RGB, 16) | (GREYSCALE_ALPHA, 8) | (GREYSCALE_ALPHA, 16) => true,
+            (RGBA, 8) | (RGBA, 16) => true,
+            _ => false,
+        };
+        if !bit_depth_valid {
+            return Err(DecodeError::Unsupported("colour type or bit depth"));
+        }
+        if data[12] != 0 {
+            return Err(DecodeError::Unsupported("interlacing"));
+        }
+
+        // Rejects empty images, and sizes that would overflow while decoding
+        let row_length = header.width
+            .checked_mul(header.bits_per_pixel())
+            .and_then(|bits| bits.checked_add(7))
+            .map(|bits| bits / 8);
+        let size = row_length.and_then(|length| (length + 1).checked_mul(header.height));
+        let pixels = header.width.checked_mul(header.height);
+        match (row_length, size, pixels) {
+            (Some(row_length), Some(_), Some(pixels)) if pixels != 0 => {
+                header.row_length = row_length;
+                Ok(header)
+            }
+            _ => Err(DecodeError::Invalid("image size")),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self.colour_type {
+            RGB => 3,
+            GREYSCALE_ALPHA => 2,
+            RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth
+    }
+
+    fn pixel(&self, row: &[u8], x: usize, palette: &[u32]) -> Result<u32, DecodeError> {
+        let grey = |level: u32| level << 16 | level << 8 | level;
+        if self.bit_depth < 8 {
+            let bit = x * self.bit_depth;
+            let sample = (row[bit / 8] >> (8 - self.bit_depth - bit % 8)) as usize &
+                         ((1 << self.bit_depth) - 1);
+            return match self.colour_type {
+                INDEXED => palette_colour(palette, sample),
+                _ => Ok(grey((sample * 255 / ((1 << self.bit_depth) - 1)) as u32)),
+            };
+        }
+
+        // 16 bit samples are cut to their high byte
+        let bytes = self.bit_depth / 8;
+        let offset = x * self.channels() * bytes;
+        let sample = |channel: usize| row[offset + channel * bytes] as u32;
+        match self.colour_type {
+            INDEXED => palette_colour(palette, sample(0) as usize),
+            GREYSCALE | GREYSCALE_ALPHA => Ok(grey(sample(0))),
+            _ => Ok(sample(0) << 16 | sample(1) << 8 | sample(2)),
+        }
+    }
+}
+
+fn palette_colour(palette: &[u32], index: usize) -> Result<u32, DecodeError> {
+    palette.get(index).cloned().ok_or(DecodeError::Invalid("palette index"))
+}
+
+// Undoes the filter of every row, and strips the filter types
+fn unfilter(header: &Header, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let row_length = header.row_length;
+    if data.len() < (row_length + 1) * header.height {
+        return Err(DecodeError::Truncated);
+    }
+    // Distance to the corresponding byte of the previous pixel
+    let bpp = ((header.bits_per_pixel() + 7) / 8) as usize;
+
+    let mut rows = vec![0; row_length * header.height];
+    for y in 0..header.height {
+        let filter = data[y * (row_length + 1)];
+        let line = &data[y * (row_length + 1) + 1..(y + 1) * (row_length + 1)];
+        let (previous, current) = rows.split_at_mut(y * row_length);
+        let above = if y > 0 { &previous[(y - 1) * row_length..] } else { &[][..] };
+        let current = &mut current[..row_length];
+
+        for i in 0..row_length {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = above.get(i).cloned().unwrap_or(0);
+            let c = if i >= bpp { above.get(i - bpp).cloned().unwrap_or(0) } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(DecodeError::Invalid("filter type")),
+            };
+            current[i] = line[i].wrapping_add(predictor)
+        }
+    }
+    Ok(rows)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> Result<u32, DecodeError> {
+        let byte = *self.bytes.get(self.pos).ok_or(DecodeError::Truncated)?;
+        let bit = (byte as u32 >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1
+        }
+        Ok(bit)
+    }
+
+    // Least significant bit first
+    fn bits(&mut self, count: u32) -> Result<u32, DecodeError> {
+        let mut val = 0;
+        for i in 0..count {
+            val |= self.bit()? << i
+        }
+        Ok(val)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1
+        }
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the
+// symbols ordered by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l as usize == length) {
+                symbols.push(symbol as u16)
+            }
+        }
+        Huffman {
+            counts: counts,
+            symbols: symbols,
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, DecodeError> {
+        // Codes are read most significant bit first
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader.bit()? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1
+        }
+        Err(DecodeError::Invalid("Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                                  385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+                                  16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9,
+                                  9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                        14, 1, 15];
+
+// Decompresses a raw deflate stream
+fn inflate(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut reader = BitReader {
+        bytes: bytes,
+        pos: 0,
+        bit: 0,
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bit()? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                if reader.pos + 4 > bytes.len() {
+                    return Err(DecodeError::Truncated);
+                }
+                let length = bytes[reader.pos] as usize | (bytes[reader.pos + 1] as usize) << 8;
+                let start = reader.pos + 4;
+                let block = bytes.get(start..start + length).ok_or(DecodeError::Truncated)?;
+                output.extend_from_slice(block);
+                reader.pos = start + length
+            }
+            1 => {
+                let mut lengths = [0; 288 + 30];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = match symbol {
+                        0...143 => 8,
+                        144...255 => 9,
+                        256...279 => 7,
+                        280...287 => 8,
+                        _ => 5,
+                    }
+                }
+                let literals = Huffman::new(&lengths[..288]);
+                let distances = Huffman::new(&lengths[288..]);
+                inflate_block(&mut reader, &literals, &distances, &mut output)?
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut output)?
+            }
+            _ => return Err(DecodeError::Invalid("deflate block type")),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), DecodeError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &index in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_lengths[index] = reader.bits(3)? as u8
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0...15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(DecodeError::Invalid("code lengths"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(length)
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(DecodeError::Invalid("code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader,
+                 literals: &Huffman,
+                 distances: &Huffman,
+                 output: &mut Vec<u8>)
+                 -> Result<(), DecodeError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(DecodeError::Invalid("length code"));
+        }
+        let length = LENGTH_BASE[index] as usize +
+                     reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distances.decode(reader)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(DecodeError::Invalid("distance code"));
+        }
+        let distance = DISTANCE_BASE[index] as usize +
+                       reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > output.len() {
+            return Err(DecodeError::Invalid("distance"));
+        }
+        // The copy may overlap the bytes it produces
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, decode, DecodeError, Image, SIGNATURE};
+
+    // Colours of the fixture images, which are 32 by 20 pixels
+    fn fixture_pixel(x: usize, y: usize) -> u32 {
+        ((x * 8) << 16 | (y * 12) << 8 | (x + y) * 4) as u32
+    }
+
+    fn fixture() -> Image {
+        let (width, height) = (32, 20);
+        Image {
+            width: width,
+            height: height,
+            pixels: (0..width * height).map(|i| fixture_pixel(i % width, i / width)).collect(),
+        }
+    }
+
+    // Just the signature and a header
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        let mut png = encode(1, 1, &[0]);
+        png[16..20].copy_from_slice(&[(width >> 24) as u8,
+                                      (width >> 16) as u8,
+                                      (width >> 8) as u8,
+                                      width as u8]);
+        png[20..24].copy_from_slice(&[(height >> 24) as u8,
+                                      (height >> 16) as u8,
+                                      (height >> 8) as u8,
+                                      height as u8]);
+        png
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = fixture();
+        assert_eq!(decode(&encode(image.width, image.height, &image.pixels)).unwrap(), image);
+
+        // Larger than a stored deflate block
+        let pixels: Vec<u32> = (0..160 * 144).map(|i| i * 0x010203).collect();
+        let image = decode(&encode(160, 144, &pixels)).unwrap();
+        assert_eq!((image.width, image.height), (160, 144));
+        assert_eq!(image.pixels, pixels.iter().map(|pixel| pixel & 0xffffff).collect::<Vec<_>>());
+    }
+
+    // Made with zlib, using the Z_FIXED strategy for the fixed Huffman codes.
+    // Every row uses the filter type y % 5.
+    #[test]
+    fn fixed_huffman() {
+        let png = include_bytes!("../tests/screenshots/filters_fixed_huffman.png");
+        assert_eq!(decode(png).unwrap(), fixture());
+    }
+
+    // The same image, using dynamic Huffman codes
+    #[test]
+    fn dynamic_huffman() {
+        let png = include_bytes!("../tests/screenshots/filters_dynamic_huffman.png");
+        assert_eq!(decode(png).unwrap(), fixture());
+    }
+
+    #[test]
+    fn invalid_images() {
+        match decode(b"GIF89a") {
+            Err(DecodeError::NotAPng) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match decode(&SIGNATURE) {
+            Err(DecodeError::Truncated) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        for &(width, height) in [(0, 1), (1, 0), (0xffff_ffff, 0xffff_ffff)].iter() {
+            match decode(&header(width, height)) {
+                Err(DecodeError::Invalid("image size")) => {}
+                result => panic!("unexpected result {:?} for {}x{}", result, width, height),
+            }
+        }
+        // More pixels than the image data holds
+        match decode(&header(0x1000, 0x1000)) {
+            Err(DecodeError::Truncated) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Rewind keeps the newest snapshot whole. Every older snapshot is stored as
+// the XOR of itself and the snapshot after it, with runs of zero bytes (the
+// memory that didn't change) left out. Stepping back applies the newest delta
+// to the newest snapshot, and the oldest deltas can be dropped at any time to
+// stay within the memory budget.
+
+use emulator::Emulator;
+use gbc::save_state::StateError;
+
+use std::collections::VecDeque;
+
+/// Records an `Emulator` every few frames so it can be stepped backwards
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    /// Oldest first, each one turns the snapshot after it into its own
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    /// Takes a snapshot every `interval` frames, and keeps as many as fit in
+    /// `budget` bytes
+    pub fn new(interval: u32, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            budget: budget,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// To be called after every frame the emulator runs
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = emulator.save_state();
+        if let Some(latest) = self.latest.take() {
+            let delta = encode(&state, &latest);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta)
+        }
+        self.latest = Some(state);
+
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the newest snapshot and forgets it, so the next call goes
+    /// further back. Returns false once there is nothing left to rewind to.
+    /// A snapshot that fails to load, like one of another emulator, is
+    /// forgotten as well and leaves the emulator unchanged.
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> Result<bool, StateError> {
+        let state = match self.latest.take() {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            self.latest = Some(decode(&state, &delta))
+        }
+        self.frames = 0;
+
+        emulator.load_state(&state)?;
+        Ok(true)
+    }
+
+    /// Number of snapshots that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.latest.as_ref().map_or(0, |state| state.len()) + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.frames = 0
+    }
+}
+
+// A delta is the length of the target, followed by pairs of a zero run length
+// and a literal run, both lengths as LEB128
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let length = base.len().max(target.len());
+    let xor = |i: usize| {
+        base.get(i).cloned().unwrap_or(0) ^ target.get(i).cloned().unwrap_or(0)
+    };
+
+    let mut delta = Vec::new();
+    write_length(&mut delta, target.len());
+
+    let mut i = 0;
+    while i < length {
+        let zeros_start = i;
+        while i < length && xor(i) == 0 {
+            i += 1
+        }
+        let literal_start = i;
+        while i < length && xor(i) != 0 {
+            i += 1
+        }
+        write_length(&mut delta, literal_start - zeros_start);
+        write_length(&mut delta, i - literal_start);
+        delta.extend((literal_start..i).map(&xor))
+    }
+    delta
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta.iter().cloned();
+    let target_length = read_length(&mut delta);
+
+    let mut target = base.to_vec();
+    target.resize(base.len().max(target_length), 0);
+
+    let mut i = 0;
+    while i < target.len() {
+        i += read_length(&mut delta);
+        for _ in 0..read_length(&mut delta) {
+            target[i] ^= delta.next().expect("truncated rewind delta");
+            i += 1
+        }
+    }
+    target.truncate(target_length);
+    target
+}
+
+fn write_length(bytes: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        bytes.push(length as u8 | 0x80);
+        length >>= 7
+    }
+    bytes.push(length as u8)
+}
+
+fn read_length<I: Iterator<Item = u8>>(bytes: &mut I) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next().expect("truncated rewind delta");
+        length |= (byte as usize & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rewind, encode, decode};
+    use emulator::{Emulator, Options};
+    use gbc::save_state::StateError;
+    use fixtures::test_rom;
+
+    #[test]
+    fn delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut target = base.clone();
+        target[3] ^= 0xff;
+        for b in target[500..700].iter_mut() {
+            *b = 0x42
+        }
+
+        let cases = [(&base[..], &target[..]),
+                     (&base[..], &target[..900]),
+                     (&target[..10], &base[..])];
+        for &(base, target) in cases.iter() {
+            assert_eq!(&decode(base, &encode(base, target))[..], target)
+        }
+        assert!(encode(&base, &target).len() < 300);
+    }
+
+    fn emulator(fill: u8) -> Emulator {
+        Emulator::new(test_rom(fill), Options::default()).unwrap()
+    }
+
+    #[test]
+    fn step_back_through_snapshots() {
+        let mut emulator = emulator(0);
+        let mut rewind = Rewind::new(1, 1 << 20);
+        let mut states = Vec::new();
+        for _ in 0..3 {
+            emulator.run_frame();
+            rewind.record(&emulator);
+            states.push(emulator.save_state())
+        }
+
+        emulator.run_frame();
+        while let Some(state) = states.pop() {
+            assert_eq!(rewind.step_back(&mut emulator).unwrap(), true);
+            assert_eq!(emulator.save_state(), state)
+        }
+        assert_eq!(rewind.step_back(&mut emulator).unwrap(), false);
+    }
+
+    #[test]
+    fn snapshot_of_another_rom_is_dropped() {
+        let mut other = emulator(0xff);
+        let mut rewind = Rewind::new(1, 1 << 20);
+        for _ in 0..2 {
+            other.run_frame();
+            rewind.record(&other)
+        }
+
+        let mut emulator = emulator(0);
+        let before = emulator.save_state();
+        match rewind.step_back(&mut emulator) {
+            Err(StateError::WrongRom) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(emulator.save_state(), before);
+    }
+}
@@ -0,0 +1,234 @@
+// Runs an emulator without a frontend, as fast as possible, until a condition
+// decides whether the run passed or failed. Used for test ROMs and CI.
+
+use emulator::Emulator;
+
+use std::fmt;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// No condition was met before the frame limit
+    TimedOut,
+}
+
+impl Outcome {
+    /// Process exit status for the outcome
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Passed => 0,
+            Outcome::Failed => 1,
+            Outcome::TimedOut => 2,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Ends a run, checked after every frame and when LD B,B executes
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Condition {
+    /// The serial output contains `text`
+    Serial { text: String, outcome: Outcome },
+    /// The byte at `addr` is `value`
+    Memory { addr: u16, value: u8, outcome: Outcome },
+    /// LD B,B was executed by a mooneye test ROM. It passed if B, C, D, E, H
+    /// and L hold 3, 5, 8, 13, 21 and 34, and failed if they all hold 0x42.
+    Mooneye,
+    /// A blargg test ROM stored its result in cartridge RAM. 0xa001-0xa003
+    /// hold the signature 0xde 0xb0 0x61 and 0xa000 the result, 0x80 while
+    /// the test is running and 0 if it passed.
+    Blargg,
+}
+
+const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+impl Condition {
+    pub fn check(&self, emulator: &mut Emulator) -> Option<Outcome> {
+        match *self {
+            Condition::Serial { ref text, outcome } => {
+                // Capture is enabled by `Runner::add_condition`
+                let output = String::from_utf8_lossy(emulator.serial_output());
+                if output.contains(text.as_str()) {
+                    Some(outcome)
+                } else {
+                    None
+                }
+            }
+            Condition::Memory { addr, value, outcome } => {
+                if emulator.read_memory(addr) == value {
+                    Some(outcome)
+                } else {
+                    None
+                }
+            }
+            Condition::Mooneye => {
+                if !emulator.breakpoint_hit() {
+                    return None;
+                }
+                let reg = emulator.registers();
+                match [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l] {
+                    MOONEYE_PASS => Some(Outcome::Passed),
+                    MOONEYE_FAIL => Some(Outcome::Failed),
+                    _ => None,
+                }
+            }
+            Condition::Blargg => {
+                let signature = [emulator.read_memory(0xa001),
+                                 emulator.read_memory(0xa002),
+                                 emulator.read_memory(0xa003)];
+                if signature != BLARGG_SIGNATURE {
+                    return None;
+                }
+                match emulator.read_memory(0xa000) {
+                    0x80 => None,
+                    0x00 => Some(Outcome::Passed),
+                    _ => Some(Outcome::Failed),
+                }
+            }
+        }
+    }
+}
+
+/// Runs an `Emulator` headless, with input from a movie
+pub struct Runner {
+    emulator: Emulator,
+    conditions: Vec<Condition>,
+    input: Vec<u8>,
+    frame: usize,
+}
+
+impl Runner {
+    pub fn new(emulator: Emulator) -> Runner {
+        Runner {
+            emulator: emulator,
+            conditions: Vec::new(),
+            input: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    /// Conditions are checked in the order they were added
+    pub fn add_condition(&mut self, condition: Condition) {
+        if let Condition::Serial { .. } = condition {
+            self.emulator.set_serial_capture(true)
+        }
+        self.conditions.push(condition)
+    }
+
+    /// Button mask for every frame, like `Movie::frames`. The buttons of the
+    /// last frame stay pressed after the input runs out.
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = input.to_vec()
+    }
+
+    /// Runs up to `frames` frames, until a condition is met. Without any
+    /// conditions all frames are run and the run passes.
+    pub fn run(&mut self, frames: u32) -> Outcome {
+        for _ in 0..frames {
+            if let Some(&buttons) = self.input.get(self.frame) {
+                self.emulator.set_buttons(buttons)
+            }
+            self.frame += 1;
+
+            // Stopping at breakpoints lets the mooneye condition see the
+            // registers before the test ROM changes them again
+            while !self.emulator.run_to_breakpoint() {
+                if let Some(outcome) = self.check() {
+                    return outcome;
+                }
+                self.emulator.clear_breakpoint()
+            }
+            if let Some(outcome) = self.check() {
+                return outcome;
+            }
+            self.emulator.clear_breakpoint()
+        }
+
+        if self.conditions.is_empty() {
+            Outcome::Passed
+        } else {
+            Outcome::TimedOut
+        }
+    }
+
+    fn check(&mut self) -> Option<Outcome> {
+        for condition in self.conditions.iter() {
+            if let Some(outcome) = condition.check(&mut self.emulator) {
+                return Some(outcome);
+            }
+        }
+        None
+    }
+
+    /// Number of frames run so far, including one a condition ended partway
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    pub fn into_emulator(self) -> Emulator {
+        self.emulator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Runner, Condition, Outcome};
+    use emulator::{Emulator, Options};
+    use fixtures::test_rom;
+
+    // Runs `program` from the entry point, followed by JR -2
+    fn emulator(program: &[u8]) -> Emulator {
+        let mut rom = test_rom(0);
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        rom[0x100 + program.len()..0x102 + program.len()].copy_from_slice(&[0x18, 0xfe]);
+        Emulator::new(rom, Options::default()).unwrap()
+    }
+
+    #[test]
+    fn mooneye_registers_at_breakpoint() {
+        // The passing values, LD B,B, then B changes before the frame ends
+        let program = [0x06, 3, 0x0e, 5, 0x16, 8, 0x1e, 13, 0x26, 21, 0x2e, 34, 0x40, 0x06, 0];
+        let mut runner = Runner::new(emulator(&program));
+        runner.add_condition(Condition::Mooneye);
+        assert_eq!(runner.run(10), Outcome::Passed);
+        assert_eq!(runner.frame(), 1);
+    }
+
+    #[test]
+    fn serial_captured_for_conditions() {
+        // Sends 'x' with the internal clock
+        let program = [0x3e, b'x', 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02];
+        let mut emulator = emulator(&program);
+        emulator.run_frame();
+        assert!(emulator.serial_output().is_empty());
+
+        let mut runner = Runner::new(self::emulator(&program));
+        runner.add_condition(Condition::Serial {
+            text: "x".to_string(),
+            outcome: Outcome::Passed,
+        });
+        assert_eq!(runner.run(10), Outcome::Passed);
+        assert_eq!(runner.emulator().serial_output(), b"x");
+    }
+}
@@ -0,0 +1,235 @@
+use minifb::{self, Key, KeyRepeat, WindowOptions, Window};
+
+use std::env;
+use std::path::PathBuf;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+use gbc_rs::{Emulator, Options, Rewind, Movie, Button, DmgPalette, Preset, SCREEN_WIDTH,
+              SCREEN_HEIGHT};
+
+use super::start_playback;
+
+// Like on the CGB, a palette combo is only picked up while the game starts
+const PALETTE_COMBO_FRAMES: u32 = 120;
+
+// Snapshots every other frame, so holding the rewind key goes back at twice
+// the speed the game runs at
+const REWIND_INTERVAL: u32 = 2;
+const REWIND_BUDGET: usize = 64 * 1024 * 1024;
+
+const SAVE_STATE_SLOTS: [Key; 9] = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5,
+                                    Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+
+// Save states are kept next to the ROM, as rom.ss1 to rom.ss9
+fn save_state_path(rom_path: &PathBuf, slot: usize) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", slot))
+}
+
+fn save_state(emulator: &Emulator, path: &PathBuf) {
+    let result = File::create(path).and_then(|mut file| file.write_all(&emulator.save_state()));
+    match result {
+        Ok(()) => println!("Saved state to {}", path.display()),
+        Err(e) => println!("Failed to save state to {}: {}", path.display(), e),
+    }
+}
+
+fn load_state(emulator: &mut Emulator, path: &PathBuf) {
+    let mut state = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut file| file.read_to_end(&mut state)) {
+        println!("Failed to read state from {}: {}", path.display(), e);
+        return;
+    }
+    match emulator.load_state(&state) {
+        Ok(()) => println!("Loaded state from {}", path.display()),
+        Err(e) => println!("Failed to load state from {}: {}", path.display(), e),
+    }
+}
+
+fn save_movie(movie: &Movie, path: &PathBuf) {
+    let result = File::create(path).and_then(|mut file| file.write_all(&movie.to_bytes()));
+    match result {
+        Ok(()) => println!("Saved {} frame movie to {}", movie.len(), path.display()),
+        Err(e) => println!("Failed to save movie to {}: {}", path.display(), e),
+    }
+}
+
+fn keycode_to_button(keycode: Key) -> Option<Button> {
+    match keycode {
+        Key::Space => Some(Button::A),
+        Key::LeftCtrl => Some(Button::B),
+        Key::Enter => Some(Button::Start),
+        Key::RightShift => Some(Button::Select),
+        Key::Up => Some(Button::Up),
+        Key::Down => Some(Button::Down),
+        Key::Left => Some(Button::Left),
+        Key::Right => Some(Button::Right),
+        _ => None,
+    }
+}
+
+fn keys_to_buttons(keys: &[Key]) -> u8 {
+    keys.iter()
+        .filter_map(|&key| keycode_to_button(key))
+        .fold(0, |buttons, button| buttons | button.mask())
+}
+
+// A direction, optionally with A or B, selects a CGB compatibility palette
+fn combo_palette(keys: &[Key]) -> Option<DmgPalette> {
+    let buttons: Vec<Button> = keys.iter().filter_map(|&key| keycode_to_button(key)).collect();
+
+    let direction = buttons.iter()
+        .cloned()
+        .find(|button| match *button {
+            Button::Up | Button::Down | Button::Left | Button::Right => true,
+            _ => false,
+        });
+    let modifier = buttons.iter()
+        .cloned()
+        .find(|button| match *button {
+            Button::A | Button::B => true,
+            _ => false,
+        });
+
+    direction.and_then(|direction| DmgPalette::cgb_compatibility(direction, modifier))
+}
+
+pub fn run(rom_path: &PathBuf, rom_binary: Box<[u8]>, options: Options) {
+    let palette = options.dmg_palette;
+    let presets = [palette,
+                   DmgPalette::preset(Preset::PocketGrey),
+                   DmgPalette::preset(Preset::OriginalGreen),
+                   DmgPalette::preset(Preset::HighContrast)];
+    let mut preset_index = 0;
+
+    let playback = env::var_os("GBC_PLAY").map(PathBuf::from).and_then(|path| {
+        match start_playback(&path, rom_binary.clone(), options.clone()) {
+            Ok(playback) => Some(playback),
+            Err(e) => {
+                println!("Failed to play {}, starting without it: {}", path.display(), e);
+                None
+            }
+        }
+    });
+    let (mut emulator, playback) = match playback {
+        Some((emulator, movie)) => (emulator, Some(movie)),
+        None => {
+            match Emulator::new(rom_binary, options) {
+                Ok(emulator) => (emulator, None),
+                Err(e) => {
+                    eprintln!("{}: {}", rom_path.display(), e);
+                    process::exit(3)
+                }
+            }
+        }
+    };
+    let mut playback_frame = 0;
+
+    // Records from power on, the movie is written on exit
+    let record_path = env::var_os("GBC_RECORD").map(PathBuf::from);
+    let mut recording = record_path.as_ref().map(|_| Movie::power_on(&emulator));
+
+    println!("{:?}", emulator.cart());
+
+    if let Err(e) = emulator.check_header() {
+        println!("Header check failed, the boot ROM will not start this cartridge: {}",
+                 e)
+    }
+
+    let mut window = Window::new("GBC_RS",
+                                 SCREEN_WIDTH,
+                                 SCREEN_HEIGHT,
+                                 WindowOptions { scale: minifb::Scale::X4, ..Default::default() })
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    let sleep_time = std::time::Duration::from_millis(16);
+
+    let mut lockup_reported = false;
+    let mut frame_count: u32 = 0;
+    let mut slot = 1;
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_BUDGET);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+
+        let now = std::time::Instant::now();
+
+        let movie_active = recording.is_some() || playback.is_some();
+        if let Some(ref movie) = playback {
+            match movie.frames().get(playback_frame) {
+                Some(&buttons) => emulator.set_buttons(buttons),
+                None if playback_frame == movie.len() => println!("Movie finished"),
+                None => (),
+            }
+            playback_frame += 1
+        }
+        if let Some(ref mut movie) = recording {
+            movie.record(emulator.buttons())
+        }
+
+        // Rewinding would make movies diverge from the run
+        if window.is_key_down(Key::Backspace) && !movie_active {
+            if let Err(e) = rewind.step_back(&mut emulator) {
+                println!("Dropped a rewind snapshot that failed to load: {}", e)
+            }
+        } else {
+            emulator.run_frame();
+            rewind.record(&emulator)
+        }
+
+        if let Some(lockup) = emulator.lockup() {
+            if !lockup_reported {
+                println!("CPU locked up: {}", lockup);
+                lockup_reported = true
+            }
+        }
+
+        window.update_with_buffer(emulator.framebuffer());
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            preset_index = (preset_index + 1) % presets.len();
+            emulator.set_dmg_palette(presets[preset_index])
+        }
+
+        for (index, &key) in SAVE_STATE_SLOTS.iter().enumerate() {
+            if window.is_key_pressed(key, KeyRepeat::No) {
+                slot = index + 1;
+                println!("Save state slot {}", slot)
+            }
+        }
+        if window.is_key_pressed(Key::F5, KeyRepeat::No) {
+            save_state(&emulator, &save_state_path(&rom_path, slot))
+        }
+        if window.is_key_pressed(Key::F8, KeyRepeat::No) {
+            // Like rewinding, loading a state would make movies diverge
+            if movie_active {
+                println!("Can't load a state while a movie is recorded or played")
+            } else {
+                load_state(&mut emulator, &save_state_path(&rom_path, slot))
+            }
+        }
+
+        if let Some(keys) = window.get_keys() {
+            if frame_count < PALETTE_COMBO_FRAMES {
+                if let Some(palette) = combo_palette(&keys) {
+                    emulator.set_dmg_palette(palette)
+                }
+            }
+            if playback.as_ref().map_or(true, |movie| playback_frame >= movie.len()) {
+                emulator.set_buttons(keys_to_buttons(&keys))
+            }
+        }
+
+        frame_count += 1;
+
+        let elapsed = now.elapsed();
+        if sleep_time > elapsed {
+            let sleep = sleep_time - elapsed;
+            std::thread::sleep(sleep)
+        }
+    }
+
+    if let (Some(movie), Some(path)) = (recording, record_path) {
+        save_movie(&movie, &path)
+    }
+}
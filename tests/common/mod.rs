@@ -0,0 +1,83 @@
+// Test ROMs aren't part of the repository, apart from our own scenes in
+// tests/roms/screenshots/scenes. They are looked up in tests/roms, or in the
+// directory GBC_TEST_ROMS points at, with one directory per suite below it.
+// Any directory structure below those works.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use gbc_rs::GameboyType;
+
+/// Directory of the suite, if it exists
+pub fn rom_dir(suite: &str) -> Option<PathBuf> {
+    let root = env::var_os("GBC_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms"));
+    let dir = root.join(suite);
+    if dir.is_dir() {
+        Some(dir)
+    } else {
+        println!("Skipping {} tests, {} doesn't exist", suite, dir.display());
+        None
+    }
+}
+
+/// All ROMs in `dir` and its subdirectories, sorted by path
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    add_roms(dir, &mut roms);
+    roms
+}
+
+fn add_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            add_roms(&path, roms)
+        } else if path.extension().map_or(false, |ext| ext == "gb" || ext == "gbc") {
+            roms.push(path)
+        }
+    }
+}
+
+// ROMs for the CGB only are named like boot_regs-cgb.gb, or have a .gbc extension
+pub fn gameboy_type(rom: &Path) -> GameboyType {
+    let name = rom.file_stem().unwrap().to_string_lossy();
+    let gbc = rom.extension().map_or(false, |ext| ext == "gbc");
+    if gbc || name.ends_with("-cgb") || name.ends_with("-C") {
+        GameboyType::Cgb
+    } else {
+        GameboyType::Dmg
+    }
+}
+
+pub fn load(path: &Path) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    bytes
+}
+
+/// A ROM without MBC or RAM, filled with `fill` and looping with JR -2 at
+/// the entry point, like the one the unit tests use
+pub fn test_rom(fill: u8) -> Box<[u8]> {
+    let mut rom = vec![fill; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+    rom[0x147..0x14a].copy_from_slice(&[0, 0, 0]);
+    rom.into_boxed_slice()
+}
+
+/// Name of the ROM relative to the suite directory
+pub fn rom_name(dir: &Path, rom: &Path) -> String {
+    rom.strip_prefix(dir).unwrap().display().to_string()
+}
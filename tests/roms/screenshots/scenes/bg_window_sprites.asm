@@ -0,0 +1,163 @@
+; Background, window and sprites of the DMG, set up once with the LCD off.
+; The reference bg_window_sprites.png was made with
+; run --headless --canonical-palette and checked pixel by pixel.
+;
+; rgbasm -o bg_window_sprites.o bg_window_sprites.asm
+; rgblink -o bg_window_sprites.gb bg_window_sprites.o
+; rgbfix -v -p 0 bg_window_sprites.gb
+
+SECTION "Header", ROM0[$0100]
+    nop
+    jp Start
+    ds $0150 - @, 0
+
+SECTION "Main", ROM0[$0150]
+Start:
+    di
+    ld sp, $fffe
+
+; The LCD may only be turned off during VBlank
+.waitVBlank:
+    ldh a, [$ff44]
+    cp 144
+    jr nz, .waitVBlank
+    xor a
+    ldh [$ff40], a
+
+    ld hl, $8000
+    ld de, Tiles
+    ld bc, TilesEnd - Tiles
+    call Copy
+
+; Diagonal bands of tiles 0 to 3
+    ld hl, $9800
+    ld d, 0
+.row:
+    ld e, 0
+.column:
+    ld a, d
+    add a, e
+    and 3
+    ld [hli], a
+    inc e
+    ld a, e
+    cp 32
+    jr nz, .column
+    inc d
+    ld a, d
+    cp 32
+    jr nz, .row
+
+; The window is all boxes
+    ld hl, $9c00
+    ld bc, $0400
+    ld d, 4
+    call Fill
+
+    ld hl, $fe00
+    ld bc, 160
+    ld d, 0
+    call Fill
+    ld hl, $fe00
+    ld de, Sprites
+    ld bc, SpritesEnd - Sprites
+    call Copy
+
+    ld a, 2
+    ldh [$ff42], a ; SCY
+    ld a, 4
+    ldh [$ff43], a ; SCX
+    ld a, 96
+    ldh [$ff4a], a ; WY
+    ld a, 95
+    ldh [$ff4b], a ; WX
+    ld a, $e4
+    ldh [$ff47], a ; BGP
+    ldh [$ff48], a ; OBP0
+    ld a, $1b
+    ldh [$ff49], a ; OBP1
+
+; LCD, window at $9c00, window, tiles at $8000, sprites and background on
+    ld a, $f3
+    ldh [$ff40], a
+
+; Waits for the third VBlank, then LD B,B ends the test
+    ld c, 3
+.frame:
+    ldh a, [$ff44]
+    cp 144
+    jr z, .frame
+.vblank:
+    ldh a, [$ff44]
+    cp 144
+    jr nz, .vblank
+    dec c
+    jr nz, .frame
+    ld b, b
+.done:
+    jr .done
+
+; hl: destination, de: source, bc: length
+Copy:
+    ld a, [de]
+    ld [hli], a
+    inc de
+    dec bc
+    ld a, b
+    or c
+    jr nz, Copy
+    ret
+
+; hl: destination, bc: length, d: value
+Fill:
+    ld a, d
+    ld [hli], a
+    dec bc
+    ld a, b
+    or c
+    jr nz, Fill
+    ret
+
+Tiles:
+; 0: blank
+    ds 16, 0
+; 1: solid
+    ds 16, $ff
+; 2: checkerboard
+REPT 4
+    dw `12121212
+    dw `21212121
+ENDR
+; 3: diagonal stripes
+REPT 2
+    dw `22002200
+    dw `02200220
+    dw `00220022
+    dw `20022002
+ENDR
+; 4: box
+    dw `33333333
+REPT 6
+    dw `31111113
+ENDR
+    dw `33333333
+; 5: arrow, for the sprites
+    dw `33330000
+    dw `32200000
+    dw `32100000
+    dw `30010000
+    dw `00001000
+    dw `00000100
+    dw `00000010
+    dw `00000001
+TilesEnd:
+
+; Y, X, tile and attributes
+Sprites:
+    db 40, 40, 5, $00
+    db 40, 48, 5, $20 ; X flip
+    db 48, 40, 5, $40 ; Y flip
+    db 48, 48, 5, $70 ; both flips, OBP1
+    db 60, 120, 5, $80 ; behind the background colours 1 to 3
+    db 60, 122, 5, $00 ; hidden by the background where the last one is opaque
+SpritesEnd:
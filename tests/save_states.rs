@@ -0,0 +1,192 @@
+// Loads save states into a machine running a small boot ROM and cartridge,
+// both built by the tests.
+
+extern crate gbc_rs;
+
+mod common;
+
+use common::test_rom;
+use gbc_rs::{Emulator, Options, StateError};
+
+// LD A,1; LDH (0x50),A unmaps the boot ROM, the cartridge runs from 0x0004
+fn boot_rom() -> Box<[u8]> {
+    let mut boot_rom = vec![0; 0x100];
+    boot_rom[..4].copy_from_slice(&[0x3e, 0x01, 0xe0, 0x50]);
+    boot_rom.into_boxed_slice()
+}
+
+// NOPs up to 0x0100, where JR -2 loops forever
+fn emulator() -> Emulator {
+    let mut options = Options::default();
+    options.boot_rom = Some(boot_rom());
+    Emulator::new(test_rom(0), options).unwrap()
+}
+
+fn read_u32(state: &[u8], offset: usize) -> usize {
+    state[offset..offset + 4].iter().rev().fold(0, |value, &b| value << 8 | b as usize)
+}
+
+fn write_u32(state: &mut [u8], offset: usize, value: usize) {
+    for i in 0..4 {
+        state[offset + i] = (value >> (i * 8)) as u8;
+    }
+}
+
+// Offset of the contents of a section, after its tag and length
+fn section_offset(state: &[u8], tag: &[u8; 4]) -> usize {
+    let mut offset = 4 + 2 + 1 + 8;
+    while &state[offset..offset + 4] != tag {
+        offset += 8 + read_u32(state, offset + 4)
+    }
+    offset + 8
+}
+
+// Offset after a length prefixed byte string
+fn skip_bytes(state: &[u8], offset: usize) -> usize {
+    offset + 4 + read_u32(state, offset)
+}
+
+#[test]
+fn boot_rom_state_after_unmapping() {
+    let mut emulator = emulator();
+    let during_boot = emulator.save_state();
+    assert_eq!(emulator.read_memory(0x0000), 0x3e);
+
+    emulator.run_frame();
+    assert_eq!(emulator.read_memory(0x0000), 0x00);
+
+    emulator.load_state(&during_boot).unwrap();
+    assert_eq!(emulator.registers().pc, 0x0000);
+    assert_eq!(emulator.read_memory(0x0000), 0x3e);
+    assert_eq!(emulator.save_state(), during_boot);
+}
+
+#[test]
+fn failed_load_leaves_machine_unchanged() {
+    let mut emulator = emulator();
+    emulator.run_frame();
+    let before = emulator.save_state();
+
+    let mut other = self::emulator();
+    for _ in 0..3 {
+        other.run_frame();
+    }
+    let state = other.save_state();
+    match emulator.load_state(&state[..state.len() - 1]) {
+        Err(StateError::SectionLength(_)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(emulator.save_state(), before);
+}
+
+#[test]
+fn running_oam_dma_past_the_end() {
+    let mut emulator = emulator();
+    let mut state = emulator.save_state();
+
+    // Boot ROM flag, RAM, HRAM, SB, SC, serial cycles and the registers up to
+    // the OAM DMA source register
+    let mut offset = section_offset(&state, b"BUS ") + 1;
+    offset = skip_bytes(&state, offset);
+    offset = skip_bytes(&state, offset);
+    offset += 1 + 1 + 4 + 9;
+    // Active, source 0xc000, index 160
+    state[offset..offset + 5].copy_from_slice(&[1, 0x00, 0xc0, 160, 0]);
+
+    let before = emulator.save_state();
+    match emulator.load_state(&state) {
+        Err(StateError::Invalid("OAM DMA index")) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(emulator.save_state(), before);
+
+    state[offset + 3] = 159;
+    emulator.load_state(&state).unwrap();
+}
+
+// Pixels after a colour table, packed into as few bits per pixel as it needs,
+// or raw without one. Returns them and the offset after them.
+fn read_pixels(state: &[u8], offset: usize, count: usize) -> (Vec<u32>, usize) {
+    let colours = state[offset] as usize | (state[offset + 1] as usize) << 8;
+    let offset = offset + 2;
+    if colours == 0 {
+        let pixels = (0..count).map(|i| read_u32(state, offset + i * 4) as u32).collect();
+        return (pixels, offset + count * 4);
+    }
+    let table: Vec<u32> = (0..colours).map(|i| read_u32(state, offset + i * 4) as u32).collect();
+    let packed = offset + colours * 4;
+    let bits = *[1, 2, 4, 8].iter().find(|&&bits| colours <= 1 << bits).unwrap();
+    let pixels = (0..count).map(|i| {
+        let byte = state[packed + 4 + i * bits / 8] as usize;
+        table[byte >> (i * bits % 8) & ((1 << bits) - 1)]
+    }).collect();
+    (pixels, skip_bytes(state, packed))
+}
+
+#[test]
+fn version_1_state_without_serial_cycles() {
+    let mut emulator = emulator();
+    emulator.run_frame();
+    let state = emulator.save_state();
+    let mut old = state.clone();
+
+    // Version 1 stored both frames raw, the one being drawn in full
+    let ppu = section_offset(&state, b"PPU ");
+    let mut offset = ppu + 18;
+    for _ in 0..4 {
+        offset = skip_bytes(&state, offset);
+    }
+    offset += 1;
+    offset = skip_bytes(&state, offset + 1 + state[offset] as usize);
+    let (back, end) = read_pixels(&state, offset, 0);
+    let (front, end) = read_pixels(&state, end, 160 * 144);
+    assert!(back.is_empty());
+    let mut raw = vec![0; 160 * 144 * 4];
+    for pixel in front {
+        raw.extend((0..4).map(|i| (pixel >> (i * 8)) as u8));
+    }
+    let length = read_u32(&old, ppu - 4) + raw.len() - (end - offset);
+    write_u32(&mut old, ppu - 4, length);
+    old.splice(offset..end, raw);
+
+    // Boot ROM flag, RAM, HRAM, SB and SC come before the serial cycles
+    let bus = section_offset(&state, b"BUS ");
+    let mut offset = skip_bytes(&state, bus + 1);
+    offset = skip_bytes(&state, offset) + 1 + 1;
+    assert_eq!(read_u32(&state, offset), 0);
+    assert!(offset < ppu);
+
+    old.drain(offset..offset + 4);
+    old[4..6].copy_from_slice(&[1, 0]);
+    let length = read_u32(&old, bus - 4);
+    write_u32(&mut old, bus - 4, length - 4);
+
+    let mut other = self::emulator();
+    other.load_state(&old).unwrap();
+    assert_eq!(other.save_state(), state);
+}
+
+#[test]
+fn ppu_state_out_of_range() {
+    let mut emulator = Emulator::new(test_rom(0), Options::default()).unwrap();
+    emulator.run_frame();
+    let state = emulator.save_state();
+
+    // STAT, LY and the window line counter follow the PPU section header
+    let ppu = section_offset(&state, b"PPU ");
+    let (stat, ly, window_line) = (ppu + 2, ppu + 5, ppu + 13);
+    let cases: [(&[(usize, u8)], &str); 3] = [(&[(ly, 250)], "LY"),
+                                              (&[(stat, 0x82), (ly, 144)], "LY"),
+                                              (&[(window_line, 145)], "window line")];
+    for &(changes, what) in cases.iter() {
+        let mut invalid = state.clone();
+        for &(offset, value) in changes {
+            invalid[offset] = value
+        }
+        match emulator.load_state(&invalid) {
+            Err(StateError::Invalid(error)) if error == what => {}
+            result => panic!("unexpected result {:?} for {}", result, what),
+        }
+        assert_eq!(emulator.save_state(), state);
+    }
+}
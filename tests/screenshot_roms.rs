@@ -0,0 +1,118 @@
+// Runs the ROMs in the screenshots directory of the test ROMs (see
+// common/mod.rs) and compares their last frame with a reference PNG next to
+// the ROM, rom.png for rom.gb. dmg-acid2, cgb-acid2 and the mealybug tearoom
+// tests execute LD B,B once the frame is complete, other ROMs run for
+// MAX_FRAMES. DMG ROMs use the canonical palette of the references.
+//
+// On a mismatch the frame is written to rom.actual.png, and rom.diff.png
+// shows the differing pixels in red, in the screenshots directory of
+// CARGO_TARGET_TMPDIR.
+
+extern crate gbc_rs;
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use gbc_rs::{Emulator, Options, DmgPalette, SCREEN_WIDTH, SCREEN_HEIGHT};
+use gbc_rs::png;
+
+const MAX_FRAMES: u32 = 60 * 10;
+
+const DIFF_COLOUR: u32 = 0xff0000;
+
+fn run_rom(rom: &Path) -> Result<Vec<u32>, String> {
+    let mut options = Options::default();
+    options.gameboy_type = common::gameboy_type(rom);
+    options.dmg_palette = DmgPalette::canonical();
+
+    let mut emulator = Emulator::new(common::load(rom).into_boxed_slice(), options)
+        .map_err(|e| e.to_string())?;
+    for _ in 0..MAX_FRAMES {
+        emulator.run_frame();
+        if emulator.breakpoint_hit() {
+            break;
+        }
+    }
+    Ok(emulator.framebuffer().iter().map(|&pixel| pixel & 0xffffff).collect())
+}
+
+// Differing pixels in red, the others darkened
+fn diff_image(frame: &[u32], reference: &[u32]) -> Vec<u32> {
+    frame.iter()
+        .zip(reference.iter())
+        .map(|(&actual, &expected)| if actual == expected {
+            (expected >> 1) & 0x7f7f7f
+        } else {
+            DIFF_COLOUR
+        })
+        .collect()
+}
+
+fn write_png(path: &Path, pixels: &[u32]) {
+    let png = png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, pixels);
+    File::create(path)
+        .and_then(|mut file| file.write_all(&png))
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+// Number of differing pixels, or why the ROM couldn't be compared
+fn compare(rom: &Path, name: &str, output_dir: &Path) -> Result<usize, String> {
+    let reference_path = rom.with_extension("png");
+    if !reference_path.exists() {
+        return Err(format!("no reference {}", reference_path.display()));
+    }
+    let reference = png::decode(&common::load(&reference_path)).map_err(|e| e.to_string())?;
+    if (reference.width, reference.height) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Err(format!("reference is {}x{}", reference.width, reference.height));
+    }
+
+    let frame = run_rom(rom)?;
+    let mismatches = frame.iter().zip(reference.pixels.iter()).filter(|&(a, b)| a != b).count();
+
+    let output = output_dir.join(name.replace('/', "_"));
+    let actual_path = output.with_extension("actual.png");
+    let diff_path = output.with_extension("diff.png");
+    if mismatches > 0 {
+        write_png(&actual_path, &frame);
+        write_png(&diff_path, &diff_image(&frame, &reference.pixels))
+    } else {
+        // Left over from an earlier run
+        let _ = fs::remove_file(actual_path);
+        let _ = fs::remove_file(diff_path);
+    }
+    Ok(mismatches)
+}
+
+#[test]
+fn screenshots() {
+    let dir = match common::rom_dir("screenshots") {
+        Some(dir) => dir,
+        None => return,
+    };
+    let output_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("screenshots");
+    fs::create_dir_all(&output_dir).unwrap();
+
+    let mut failures = Vec::new();
+    for rom in common::find_roms(&dir) {
+        let name = common::rom_name(&dir, &rom);
+        match compare(&rom, &name, &output_dir) {
+            Ok(0) => println!("{}: matches", name),
+            Ok(mismatches) => {
+                println!("{}: {} pixels differ", name, mismatches);
+                failures.push(name)
+            }
+            Err(e) => {
+                println!("{}: {}", name, e);
+                failures.push(name)
+            }
+        }
+    }
+
+    assert!(failures.is_empty(),
+            "screenshots don't match for {}, see {}",
+            failures.join(", "),
+            output_dir.display());
+}
@@ -0,0 +1,446 @@
+// Runs every instruction against the SM83 single step test vectors, JSON files
+// like 00.json and cb 00.json with a thousand tests each. They go in the sm83
+// directory of the test ROMs, see common/mod.rs.
+//
+// A test gives the registers and RAM before and after one instruction, and
+// the bus activity of every M-cycle. The CPU runs on a flat 64 KiB bus
+// without any I/O registers or interrupts.
+
+extern crate gbc_rs;
+
+mod common;
+
+use std::any::Any;
+use std::fmt::Write;
+use std::panic;
+use std::path::Path;
+use std::str;
+
+use gbc_rs::gbc::bus::Bus;
+use gbc_rs::gbc::cpu::Cpu;
+use gbc_rs::gbc::registers::{Registers, Reg8};
+
+// Reported per file, the rest are only counted
+const MAX_REPORTED: usize = 3;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+    Internal,
+}
+
+struct FlatBus {
+    memory: Vec<u8>,
+    cycles: Vec<Access>,
+}
+
+impl FlatBus {
+    fn new() -> FlatBus {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            cycles: Vec::new(),
+        }
+    }
+}
+
+// The CPU ticks before every access, so each access replaces the internal
+// M-cycle that was just recorded
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.memory[addr as usize];
+        if let Some(cycle) = self.cycles.last_mut() {
+            *cycle = Access::Read(addr, val)
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.memory[addr as usize] = val;
+        if let Some(cycle) = self.cycles.last_mut() {
+            *cycle = Access::Write(addr, val)
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            self.cycles.push(Access::Internal)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => {
+                members.iter().find(|&&(ref name, _)| name == key).map(|&(_, ref value)| value)
+            }
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> &Json {
+        self.get(key).unwrap_or_else(|| panic!("missing field {}", key))
+    }
+
+    fn number(&self) -> Option<i64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            Json::Bool(b) => Some(b as i64),
+            _ => None,
+        }
+    }
+
+    fn u8(&self) -> u8 {
+        self.number().expect("expected a number") as u8
+    }
+
+    fn u16(&self) -> u16 {
+        self.number().expect("expected a number") as u16
+    }
+
+    fn array(&self) -> &[Json] {
+        match *self {
+            Json::Array(ref elements) => elements,
+            _ => panic!("expected an array"),
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// Just enough JSON for the test vectors: no floats or escaped code points
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(bytes: &[u8]) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: bytes,
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos] as char).is_whitespace() {
+            self.pos += 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, text: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    // Calls `element` for every comma separated element up to `end`
+    fn list<F>(&mut self, end: u8, mut element: F) -> Result<(), String>
+        where F: FnMut(&mut Parser<'a>) -> Result<(), String>
+    {
+        self.pos += 1;
+        if self.peek() == Some(end) {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            element(self)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(byte) if byte == end => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected ',' or end of list")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        let mut members = Vec::new();
+        self.list(b'}', |p| {
+            if p.peek() != Some(b'"') {
+                return Err(p.error("expected a key"));
+            }
+            let key = p.string()?;
+            p.expect(b':')?;
+            members.push((key, p.value()?));
+            Ok(())
+        })?;
+        Ok(Json::Object(members))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        let mut elements = Vec::new();
+        self.list(b']', |p| {
+            elements.push(p.value()?);
+            Ok(())
+        })?;
+        Ok(Json::Array(elements))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            match self.bytes.get(self.pos).cloned() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    s.push(match self.bytes.get(self.pos).cloned() {
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(byte @ b'"') | Some(byte @ b'\\') | Some(byte @ b'/') => byte,
+                        _ => return Err(self.error("unsupported escape")),
+                    })
+                }
+                Some(byte) => s.push(byte),
+                None => return Err(self.error("unterminated string")),
+            }
+            self.pos += 1
+        }
+        self.pos += 1;
+        String::from_utf8(s).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        if self.bytes[self.pos] == b'-' {
+            self.pos += 1
+        }
+        while self.pos < self.bytes.len() && (self.bytes[self.pos] as char).is_digit(10) {
+            self.pos += 1
+        }
+        str::from_utf8(&self.bytes[start..self.pos])
+            .unwrap()
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
+
+const REGISTERS: [(&'static str, Reg8); 8] = [("a", Reg8::A),
+                                              ("f", Reg8::F),
+                                              ("b", Reg8::B),
+                                              ("c", Reg8::C),
+                                              ("d", Reg8::D),
+                                              ("e", Reg8::E),
+                                              ("h", Reg8::H),
+                                              ("l", Reg8::L)];
+
+fn setup(state: &Json) -> Cpu<FlatBus> {
+    let mut reg = Registers::default();
+    for &(name, r) in REGISTERS.iter() {
+        reg.write_u8(r, state.field(name).u8())
+    }
+    reg.sp = state.field("sp").u16();
+    reg.pc = state.field("pc").u16();
+
+    let mut bus = FlatBus::new();
+    for entry in state.field("ram").array() {
+        let entry = entry.array();
+        bus.memory[entry[0].u16() as usize] = entry[1].u8()
+    }
+    let ime = state.get("ime").map_or(false, |ime| ime.u8() != 0);
+    Cpu::with_registers(bus, reg, ime)
+}
+
+fn expected_access(cycle: &Json) -> Access {
+    let cycle = match *cycle {
+        Json::Array(ref cycle) if cycle.len() == 3 => cycle,
+        _ => return Access::Internal,
+    };
+    let kind = cycle[2].str().unwrap_or("");
+    match (cycle[0].number(), cycle[1].number()) {
+        (Some(addr), Some(val)) if kind.starts_with('r') => Access::Read(addr as u16, val as u8),
+        (Some(addr), Some(val)) if kind.get(1..2) == Some("w") => {
+            Access::Write(addr as u16, val as u8)
+        }
+        _ => Access::Internal,
+    }
+}
+
+// Everything that differs from the final state, empty if the test passed
+fn run_test(test: &Json) -> String {
+    let mut cpu = setup(test.field("initial"));
+    cpu.step();
+
+    let mut errors = String::new();
+    let expected = test.field("final");
+    {
+        let reg = cpu.registers();
+        for &(name, r) in REGISTERS.iter() {
+            let (actual, wanted) = (reg.read_u8(r), expected.field(name).u8());
+            if actual != wanted {
+                write!(errors, " {}=0x{:02x} (expected 0x{:02x})", name, actual, wanted).unwrap()
+            }
+        }
+        for &(name, actual) in [("sp", reg.sp), ("pc", reg.pc)].iter() {
+            let wanted = expected.field(name).u16();
+            if actual != wanted {
+                write!(errors, " {}=0x{:04x} (expected 0x{:04x})", name, actual, wanted).unwrap()
+            }
+        }
+    }
+    if let Some(ime) = expected.get("ime") {
+        let (actual, wanted) = (cpu.ime().0, ime.u8() != 0);
+        if actual != wanted {
+            write!(errors, " ime={} (expected {})", actual, wanted).unwrap()
+        }
+    }
+    for entry in expected.field("ram").array() {
+        let entry = entry.array();
+        let (addr, wanted) = (entry[0].u16(), entry[1].u8());
+        let actual = cpu.bus().memory[addr as usize];
+        if actual != wanted {
+            write!(errors,
+                   " [0x{:04x}]=0x{:02x} (expected 0x{:02x})",
+                   addr,
+                   actual,
+                   wanted)
+                .unwrap()
+        }
+    }
+
+    let cycles: Vec<Access> = test.field("cycles").array().iter().map(expected_access).collect();
+    if cpu.bus().cycles != cycles {
+        write!(errors,
+               " cycles {:?} (expected {:?})",
+               cpu.bus().cycles,
+               cycles)
+            .unwrap()
+    }
+    errors
+}
+
+fn panic_message(payload: &Any) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("?", |message| message.as_str()),
+    }
+}
+
+// Number of tests in the file, and descriptions of the first failures. A test
+// that panics fails, without stopping the others.
+fn run_file(path: &Path) -> (usize, usize, Vec<String>) {
+    let tests = Parser::parse(&common::load(path))
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let tests = tests.array();
+
+    let mut failed = 0;
+    let mut reports = Vec::new();
+    for test in tests {
+        let errors = match panic::catch_unwind(|| run_test(test)) {
+            Ok(errors) => errors,
+            Err(payload) => format!(" panicked: {}", panic_message(&*payload)),
+        };
+        if !errors.is_empty() {
+            failed += 1;
+            if reports.len() < MAX_REPORTED {
+                let name = test.get("name").and_then(Json::str).unwrap_or("?");
+                reports.push(format!("{}:{}", name, errors))
+            }
+        }
+    }
+    (tests.len(), failed, reports)
+}
+
+#[test]
+fn sm83() {
+    let dir = match common::rom_dir("sm83") {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let mut files: Vec<_> = dir.read_dir()
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    // Panics are reported with the failures instead
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let results: Vec<_> = files.iter().map(|path| run_file(path)).collect();
+    panic::set_hook(default_hook);
+
+    let mut failed_files = Vec::new();
+    let mut total = 0;
+    for (path, (count, failed, reports)) in files.iter().zip(results) {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        total += count;
+        if failed > 0 {
+            println!("{}: {} of {} tests failed", name, failed, count);
+            for report in reports {
+                println!("    {}", report)
+            }
+            failed_files.push(name)
+        }
+    }
+
+    println!("{} tests in {} files, {} files with failures",
+             total,
+             files.len(),
+             failed_files.len());
+    assert!(failed_files.is_empty(),
+            "instructions failed: {}",
+            failed_files.join(", "));
+}
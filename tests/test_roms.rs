@@ -0,0 +1,90 @@
+// Runs the blargg and mooneye test ROMs headless and prints a summary table.
+//
+// The blargg ROMs go in the blargg directory and the mooneye ROMs in the
+// mooneye directory of the test ROMs, see common/mod.rs. Tests skip when
+// their directory is missing, `cargo test -- --nocapture` shows the tables.
+
+extern crate gbc_rs;
+
+mod common;
+
+use std::path::Path;
+
+use gbc_rs::{Emulator, Options, Runner, Condition, Outcome};
+
+// blargg's cpu_instrs takes almost a minute, the mooneye tests a few seconds
+const BLARGG_FRAMES: u32 = 60 * 90;
+const MOONEYE_FRAMES: u32 = 60 * 20;
+
+// The outcome and the frames run, or why the emulator can't load the ROM
+type RomResult = Result<(Outcome, usize), String>;
+
+fn run_rom(rom: &Path, conditions: &[Condition], frames: u32) -> RomResult {
+    let mut options = Options::default();
+    options.gameboy_type = common::gameboy_type(rom);
+
+    let emulator = Emulator::new(common::load(rom).into_boxed_slice(), options)
+        .map_err(|e| e.to_string())?;
+    let mut runner = Runner::new(emulator);
+    for condition in conditions {
+        runner.add_condition(condition.clone())
+    }
+    let outcome = runner.run(frames);
+    Ok((outcome, runner.frame()))
+}
+
+// Runs every ROM in the suite and fails if any of them didn't pass
+fn run_suite(suite: &str, conditions: &[Condition], frames: u32) {
+    let dir = match common::rom_dir(suite) {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let results: Vec<(String, RomResult)> = common::find_roms(&dir)
+        .iter()
+        .map(|rom| (common::rom_name(&dir, rom), run_rom(rom, conditions, frames)))
+        .collect();
+
+    // ROMs that can't be loaded are listed as unsupported, with the error
+    let width = results.iter().map(|result| result.0.len()).max().unwrap_or(0);
+    println!("\n{:<width$}  Result       Frames", "ROM", width = width);
+    for result in results.iter() {
+        let (outcome, frames) = match result.1 {
+            Ok((outcome, frames)) => (outcome.to_string(), frames.to_string()),
+            Err(ref e) => ("unsupported".to_string(), e.clone()),
+        };
+        println!("{:<width$}  {:<11}  {}", result.0, outcome, frames, width = width)
+    }
+
+    let passed = results.iter()
+        .filter(|result| match result.1 {
+            Ok((outcome, _)) => outcome == Outcome::Passed,
+            Err(_) => false,
+        })
+        .count();
+    println!("{} of {} {} tests passed", passed, results.len(), suite);
+    assert!(passed == results.len(),
+            "{} of {} {} tests didn't pass",
+            results.len() - passed,
+            results.len(),
+            suite);
+}
+
+#[test]
+fn blargg() {
+    let conditions = [Condition::Serial {
+                          text: "Passed".to_string(),
+                          outcome: Outcome::Passed,
+                      },
+                      Condition::Serial {
+                          text: "Failed".to_string(),
+                          outcome: Outcome::Failed,
+                      },
+                      Condition::Blargg];
+    run_suite("blargg", &conditions, BLARGG_FRAMES)
+}
+
+#[test]
+fn mooneye() {
+    run_suite("mooneye", &[Condition::Mooneye], MOONEYE_FRAMES)
+}